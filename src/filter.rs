@@ -0,0 +1,98 @@
+//! Choosing which files take part in a sync using gitignore-style patterns.
+
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::{Result, SyncError};
+
+/// Decides which files take part in a sync, using include and exclude patterns. Create one
+/// using [`FileFilter::builder`].
+///
+/// Patterns use `.gitignore` syntax: `*.html` matches at any depth, `assets/**` or `/assets/`
+/// only match at the top level, and a pattern matching a directory matches everything in it.
+///
+/// A file is synced if it matches any include pattern (or there are none), and doesn't match
+/// any exclude pattern. Files that don't pass the filter are left alone on both sides, and are
+/// never deleted.
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    include: Gitignore,
+    exclude: Gitignore,
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        FileFilter {
+            include: Gitignore::empty(),
+            exclude: Gitignore::empty(),
+        }
+    }
+}
+
+impl FileFilter {
+    /// Start building a filter, which begins by matching every file.
+    pub fn builder() -> FileFilterBuilder {
+        FileFilterBuilder::default()
+    }
+
+    /// Whether the file at `path` takes part in the sync.
+    pub fn is_match(&self, path: &Path) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .matched_path_or_any_parents(path, false)
+                .is_ignore();
+        let excluded = self
+            .exclude
+            .matched_path_or_any_parents(path, false)
+            .is_ignore();
+
+        included && !excluded
+    }
+}
+
+/// Builder for [`FileFilter`].
+#[derive(Debug, Clone, Default)]
+pub struct FileFilterBuilder {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl FileFilterBuilder {
+    /// Only sync files matching `pattern`, or any other include pattern.
+    pub fn include<S: AsRef<str>>(mut self, pattern: S) -> Self {
+        self.include.push(pattern.as_ref().to_owned());
+        self
+    }
+
+    /// Never sync files matching `pattern`, even if they match an include pattern.
+    pub fn exclude<S: AsRef<str>>(mut self, pattern: S) -> Self {
+        self.exclude.push(pattern.as_ref().to_owned());
+        self
+    }
+
+    /// Build the filter, failing if any pattern is invalid.
+    pub fn build(self) -> Result<FileFilter> {
+        Ok(FileFilter {
+            include: matcher(&self.include)?,
+            exclude: matcher(&self.exclude)?,
+        })
+    }
+}
+
+fn matcher(patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new("");
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|error| SyncError::InvalidPattern {
+                pattern: pattern.clone(),
+                error,
+            })?;
+    }
+    builder.build().map_err(|error| SyncError::InvalidPattern {
+        pattern: patterns.join(", "),
+        error,
+    })
+}
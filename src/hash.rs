@@ -0,0 +1,101 @@
+//! Content hashes, for comparing files more reliably or more quickly.
+
+use serde::{Deserialize, Serialize};
+use sha2::Digest as _;
+
+/// An algorithm for hashing the contents of a file. (See [`FileEntry::digest`](crate::FileEntry::digest).)
+///
+/// MD5 hashes are also kept in [`FileEntry::md5_hash`](crate::FileEntry::md5_hash), as that is
+/// what S3 ETags hold. An MD5 digest is compared with those the same way as with other MD5
+/// digests.
+///
+/// To compare local files quickly, use [`HashAlgorithm::Blake3`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum HashAlgorithm {
+    /// SHA-256, for when a cryptographic hash is needed.
+    Sha256,
+
+    /// CRC-32C, for S3's `x-amz-checksum-crc32c` checksums. At 32 bits, different files are
+    /// too likely to share a checksum for it to be relied on when deciding what to sync.
+    Crc32c,
+
+    /// MD5, which is what S3 uses as the ETag of objects uploaded in a single request.
+    Md5,
+
+    /// BLAKE3, a cryptographic hash that is much faster to compute than SHA-256. S3 has no
+    /// BLAKE3 checksum.
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Start hashing some data a chunk at a time.
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Crc32c => Hasher::Crc32c(0),
+            HashAlgorithm::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    /// Hash `bytes` in one go.
+    pub fn digest(self, bytes: &[u8]) -> Digest {
+        let mut hasher = self.hasher();
+        hasher.update(bytes);
+        hasher.finish()
+    }
+}
+
+/// The hash of a file's contents, tagged with the algorithm that produced it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Digest {
+    pub algorithm: HashAlgorithm,
+
+    /// The hash, in the byte order the algorithm defines. CRC-32C values are big-endian,
+    /// like the checksums S3 returns, and MD5 values are in the same order as
+    /// [`FileEntry::md5_hash`](crate::FileEntry::md5_hash) is.
+    pub value: Vec<u8>,
+}
+
+/// An in-progress hash.
+pub(crate) enum Hasher {
+    Sha256(sha2::Sha256),
+    Crc32c(u32),
+    Md5(md5::Context),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, bytes),
+            Hasher::Md5(context) => context.consume(bytes),
+            Hasher::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+    }
+
+    pub fn finish(self) -> Digest {
+        match self {
+            Hasher::Sha256(hasher) => Digest {
+                algorithm: HashAlgorithm::Sha256,
+                value: hasher.finalize().to_vec(),
+            },
+            Hasher::Crc32c(crc) => Digest {
+                algorithm: HashAlgorithm::Crc32c,
+                value: crc.to_be_bytes().to_vec(),
+            },
+            Hasher::Md5(context) => Digest {
+                algorithm: HashAlgorithm::Md5,
+                value: context.compute().to_vec(),
+            },
+            Hasher::Blake3(hasher) => Digest {
+                algorithm: HashAlgorithm::Blake3,
+                value: hasher.finalize().as_bytes().to_vec(),
+            },
+        }
+    }
+}
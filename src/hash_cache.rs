@@ -0,0 +1,143 @@
+//! Remembering the hashes of local files between listings, so unchanged files aren't read
+//! again.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Digest, HashAlgorithm};
+
+/// What a file looked like when it was hashed. If any of this changes, the hash is stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CacheKey {
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+    pub inode: Option<u64>,
+
+    /// Whether [`FileEntry::md5_hash`](crate::FileEntry::md5_hash) was computed.
+    pub md5: bool,
+
+    /// The part size used if that hash is a multipart hash.
+    pub part_size: Option<u64>,
+
+    /// The algorithm of the [`Digest`] that was computed, which may be any
+    /// [`HashAlgorithm`], or `None` if there isn't one.
+    pub algorithm: Option<HashAlgorithm>,
+}
+
+/// The hashes computed for a single file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FileHashes {
+    pub md5_hash: Option<u128>,
+    pub md5_parts: Option<u32>,
+    pub digest: Option<Digest>,
+}
+
+/// A file's MD5 hash and digest, serialized the same way as in [`FileEntry`](crate::FileEntry),
+/// along with the key they were computed for.
+///
+/// The key isn't flattened into the entry, as serde can't read 128-bit integers back out of a
+/// flattened struct.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedHash {
+    key: CacheKey,
+    md5_hash: Option<u128>,
+    md5_parts: Option<u32>,
+    digest: Option<Digest>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    files: HashMap<PathBuf, CachedHash>,
+}
+
+/// The hashes stored by the previous listing, and the ones found by this one.
+///
+/// Only files seen by this listing are saved, so entries for deleted files are dropped.
+pub(crate) struct HashCache {
+    path: Option<PathBuf>,
+    previous: HashMap<PathBuf, CachedHash>,
+    current: HashMap<PathBuf, CachedHash>,
+    changed: bool,
+}
+
+impl HashCache {
+    /// Load the cache stored at `path`. A missing or unreadable cache is treated as empty,
+    /// and if `path` is `None`, nothing is cached.
+    pub fn load(path: Option<&Path>) -> Self {
+        let previous = path
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice::<CacheFile>(&bytes).ok())
+            .unwrap_or_default()
+            .files;
+
+        HashCache {
+            path: path.map(Path::to_owned),
+            previous,
+            current: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Get the hashes of the file at `path`, calling `compute` unless they were stored for
+    /// the same `key`.
+    pub fn hash<E, F>(&mut self, path: &Path, key: CacheKey, compute: F) -> Result<FileHashes, E>
+    where
+        F: FnOnce() -> Result<FileHashes, E>,
+    {
+        let stored = self
+            .previous
+            .remove(path)
+            .filter(|cached| cached.key == key)
+            .map(|cached| FileHashes {
+                md5_hash: cached.md5_hash,
+                md5_parts: cached.md5_parts,
+                digest: cached.digest,
+            });
+
+        let hashes = match stored {
+            Some(hashes) => hashes,
+            None => {
+                self.changed = true;
+                compute()?
+            }
+        };
+
+        if self.path.is_some() {
+            let cached = CachedHash {
+                key,
+                md5_hash: hashes.md5_hash,
+                md5_parts: hashes.md5_parts,
+                digest: hashes.digest.clone(),
+            };
+            self.current.insert(path.to_owned(), cached);
+        }
+        Ok(hashes)
+    }
+
+    /// Save the hashes found by this listing, if anything changed. The file is written
+    /// beside the old one and renamed over it.
+    pub fn save(self) -> std::io::Result<()> {
+        let Some(path) = self.path else {
+            return Ok(());
+        };
+        if !self.changed && self.previous.is_empty() {
+            return Ok(());
+        }
+
+        let cache = CacheFile {
+            files: self.current,
+        };
+        let temp_path = crate::local::temp_path(&path);
+        let result = std::fs::write(&temp_path, serde_json::to_vec(&cache)?)
+            .and_then(|()| std::fs::rename(&temp_path, &path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result
+    }
+}
@@ -14,32 +14,64 @@
 //! let config = aws_config::load_from_env().await;
 //! let client = aws_sdk_s3::Client::new(&config);
 //!
-//! let mut local = LocalFiles::new("./my_local_files", true);
-//! let mut s3 = S3Files::new(client, "my_s3_bucket", "path/in/bucket", true);
+//! let local = LocalFiles::new("./my_local_files", true);
+//! let s3 = S3Files::new(client, "my_s3_bucket", "path/in/bucket", true);
 //!
-//! let synced_paths = filesync::sync_one_way(&mut local, &mut s3).await?;
+//! let synced_paths = filesync::sync_one_way(&local, &s3).await?;
 //! assert_eq!(synced_paths, vec![PathBuf::from("my_changed_file.txt")]);
 //! # Ok(())
 //! # }
 //! ```
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     path::{Path, PathBuf},
+    pin::Pin,
     result::Result as StdResult,
+    sync::Arc,
 };
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
 use thiserror::Error as ErrorTrait;
 
+mod filter;
+mod hash;
+mod hash_cache;
 pub mod local;
+mod options;
+mod plan;
+mod progress;
+mod read_only;
+mod resolve;
+mod retry;
 
 #[cfg(feature = "s3")]
 pub mod s3;
 
 mod tests;
 
+pub use filter::{FileFilter, FileFilterBuilder};
+pub use hash::{Digest, HashAlgorithm};
+pub use options::{ModifiedTimeSync, SyncOptions, SyncOptionsBuilder};
+pub use plan::{
+    ChangeReason, SyncAction, SyncActionKind, SyncPlan, execute, execute_with, plan_sync,
+    plan_sync_with,
+};
+pub use progress::{SyncEvent, SyncObserver};
+pub use read_only::{ReadOnly, ReadOnlyError};
+pub use resolve::{ConflictResolver, ConflictStrategy, Resolution, conflict_path};
+pub use retry::RetryPolicy;
+
+/// The smallest part size S3 accepts for every part of a multipart upload except the last.
+///
+/// Both `S3Files::part_size` and [`LocalFiles::multipart_md5`](local::LocalFiles::multipart_md5)
+/// treat smaller part sizes as this, so that they agree on how files are split.
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
 /// Error type for this crate.
 #[derive(Debug, ErrorTrait)]
 pub enum SyncError {
@@ -49,12 +81,27 @@ pub enum SyncError {
     #[error("Errors occurred while comparing files. No changes have been written:\n{}", errors.iter().map(SyncError::to_string).collect::<Vec<String>>().join("\n"))]
     ErrorComparing { errors: Vec<SyncError> },
 
+    #[error("File `{}` differs between the source and destination", filename.display())]
+    Conflict { filename: PathBuf },
+
     #[error(transparent)]
-    FileSourceError(#[from] Box<dyn std::error::Error + Send>),
+    FileSourceError(#[from] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Failed to load or save sync state: {0}")]
+    StateError(#[from] std::io::Error),
+
+    #[error("File `{}` failed verification after being written: {reason}", filename.display())]
+    VerificationFailed { filename: PathBuf, reason: String },
+
+    #[error("Invalid filter pattern `{pattern}`: {error}")]
+    InvalidPattern {
+        pattern: String,
+        error: ignore::Error,
+    },
 }
 
 impl SyncError {
-    fn boxed<E: std::error::Error + Send + 'static>(error: E) -> Self {
+    fn boxed<E: std::error::Error + Send + Sync + 'static>(error: E) -> Self {
         SyncError::FileSourceError(Box::new(error))
     }
 }
@@ -63,12 +110,69 @@ impl SyncError {
 pub type Result<T> = StdResult<T, SyncError>;
 
 /// Represents a file at a path with some metadata.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileEntry {
     pub path: PathBuf,
     pub modified: Option<DateTime<Utc>>,
     pub size: Option<u64>,
     pub md5_hash: Option<u128>,
+
+    /// If set, `md5_hash` is a multipart hash of this many parts instead of an MD5 hash of
+    /// the whole file. This is how S3 calculates the ETag of an object uploaded in parts: the
+    /// MD5 hash of the concatenated MD5 hashes of each part.
+    ///
+    /// Hashes are only compared if both files have the same number of parts.
+    #[serde(default)]
+    pub md5_parts: Option<u32>,
+
+    /// If set, the file is a symbolic link to this path, rather than a regular file. Its
+    /// size and hash are those of the target path itself, not of the file it points to.
+    ///
+    /// Syncing a link recreates it with [`FileSource::write_link`].
+    #[serde(default)]
+    pub link_target: Option<PathBuf>,
+
+    /// Another hash of the file's contents, if the source computes one. (See
+    /// [`HashAlgorithm`].)
+    ///
+    /// Digests are only compared with hashes from the same algorithm, including
+    /// [`FileEntry::md5_hash`] for MD5 digests. Every hash that can be compared must match.
+    #[serde(default)]
+    pub digest: Option<Digest>,
+
+    /// The file's Unix permissions and ownership, if the source records them. Only synced
+    /// when [`SyncOptions::permissions`] is set.
+    #[serde(default)]
+    pub permissions: Option<FilePermissions>,
+}
+
+/// The Unix permissions of a file, and optionally its owner and group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePermissions {
+    /// The permission bits, such as `0o755`, including the setuid, setgid and sticky bits.
+    pub mode: u32,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FilePermissions {
+    /// Whether `self` differs from `other`. The owner and group are only compared if both
+    /// have them.
+    pub fn differs_from(&self, other: &FilePermissions) -> bool {
+        fn differs(a: Option<u32>, b: Option<u32>) -> bool {
+            matches!((a, b), (Some(a), Some(b)) if a != b)
+        }
+
+        self.mode != other.mode || differs(self.uid, other.uid) || differs(self.gid, other.gid)
+    }
+}
+
+/// Attributes of a file that a source may be able to store as part of writing it, instead of
+/// setting them afterwards. (See [`FileSource::write_stream_with`].)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub modified: Option<DateTime<Utc>>,
+    pub permissions: Option<FilePermissions>,
 }
 
 impl FileEntry {
@@ -76,25 +180,30 @@ impl FileEntry {
     ///
     /// The rules are as following:
     ///
-    /// 1. If the size and MD5 hash match, the files are considered to be the
-    ///    same.
+    /// 1. If the size and hashes match, the files are considered to be the same. Every hash
+    ///    both files have from the same algorithm is compared, whether it is the
+    ///    [`FileEntry::digest`] or the MD5 hash. (Multipart MD5 hashes are only compared if
+    ///    [`FileEntry::md5_parts`] matches too.)
     /// 2. Failing that, if a modified time is present for both files, the most recent one takes
     ///    precedence.
-    /// 3. Failing that, if either the size or MD5 hash are different, the files
+    /// 3. Failing that, if either the size or hash are different, the files
     ///    are considered to be different.
     ///
     /// In the absence of a modified time, any change is considered to be an
     /// "update".
     pub fn is_changed_from(&self, other: &FileEntry) -> Result<bool> {
+        Ok(self.compare(other)?.is_change())
+    }
+
+    /// Compares two files in the same way as [`FileEntry::is_changed_from`], but returns the
+    /// rule which made the decision.
+    pub fn compare(&self, other: &FileEntry) -> Result<ChangeReason> {
         let size_different = match (self.size, other.size) {
             (Some(a), Some(b)) => Some(a != b),
             _ => None,
         };
 
-        let hash_different = match (self.md5_hash, other.md5_hash) {
-            (Some(a), Some(b)) => Some(a != b),
-            _ => None,
-        };
+        let hash_different = self.hash_matches(other).map(|matches| !matches);
 
         let date_later = match (self.modified, other.modified) {
             (Some(a), Some(b)) => Some(a > b),
@@ -103,16 +212,16 @@ impl FileEntry {
 
         Ok(match (size_different, hash_different, date_later) {
             // Size and md5 unchanged -> file is unchanged
-            (Some(false), Some(false), _) => false,
+            (Some(false), Some(false), _) => ChangeReason::SameSizeAndHash,
 
             // Next, modified date is the arbiter if present
-            (_, _, Some(true)) => true,
-            (_, _, Some(false)) => false,
+            (_, _, Some(true)) => ChangeReason::ModifiedLater,
+            (_, _, Some(false)) => ChangeReason::NotModifiedLater,
 
             // Without a date present size/hash determine changes
-            (Some(x), Some(y), None) => x || y,
-            (Some(x), None, None) => x,
-            (None, Some(x), None) => x,
+            (Some(x), Some(y), None) => ChangeReason::size_or_hash(x || y),
+            (Some(x), None, None) => ChangeReason::size_or_hash(x),
+            (None, Some(x), None) => ChangeReason::size_or_hash(x),
 
             (None, None, None) => {
                 return Err(SyncError::NoMetadata {
@@ -121,40 +230,228 @@ impl FileEntry {
             }
         })
     }
+
+    /// Checks whether `self` is the same as `base`, an earlier entry for the same file.
+    ///
+    /// Every piece of metadata present on both entries must match. If there is nothing to
+    /// compare, the file is assumed to have changed.
+    pub fn is_unchanged_since(&self, base: &FileEntry) -> bool {
+        fn matches<T: PartialEq>(a: Option<T>, b: Option<T>) -> Option<bool> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a == b),
+                _ => None,
+            }
+        }
+
+        let checks = [
+            matches(self.size, base.size),
+            self.hash_matches(base),
+            matches(self.modified, base.modified),
+        ];
+
+        checks.iter().any(Option::is_some) && checks.iter().all(|x| *x != Some(false))
+    }
+
+    /// Every hash of the file's whole contents, including a plain MD5 hash as an MD5
+    /// digest. Multipart MD5 hashes aren't included.
+    pub(crate) fn digests(&self) -> impl Iterator<Item = Digest> + '_ {
+        let md5 = self
+            .md5_hash
+            .filter(|_| self.md5_parts.is_none())
+            .map(|hash| Digest {
+                algorithm: HashAlgorithm::Md5,
+                value: hash.to_be_bytes().to_vec(),
+            });
+        self.digest.iter().cloned().chain(md5)
+    }
+
+    /// Whether the contents of both files hash the same, if they have any hashes calculated
+    /// in the same way. Every such hash must match.
+    fn hash_matches(&self, other: &FileEntry) -> Option<bool> {
+        let mut matches = None;
+        for a in self.digests() {
+            for b in other.digests().filter(|b| b.algorithm == a.algorithm) {
+                matches = Some(matches.unwrap_or(true) && a.value == b.value);
+            }
+        }
+
+        if let (Some(a), Some(b), Some(parts)) = (self.md5_hash, other.md5_hash, self.md5_parts)
+            && other.md5_parts == Some(parts)
+        {
+            matches = Some(matches.unwrap_or(true) && a == b);
+        }
+        matches
+    }
 }
 
+/// A file's contents, read as a stream of chunks.
+///
+/// Errors part-way through a stream are reported as [`std::io::Error`]s, so that a stream read
+/// from one kind of [`FileSource`] can be written to another.
+pub type FileStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
 /// The trait that powers the sync function. Implemented using the [async_trait](https://docs.rs/async-trait/latest/async_trait/) crate.
 ///
 /// You shouldn't need to manually deal with this trait unless you are implementing it for an
 /// otherwise unsupported data storage.
 #[async_trait]
-pub trait FileSource {
-    type Error: std::error::Error + Send + 'static;
+pub trait FileSource: Send + Sync {
+    type Error: std::error::Error + From<std::io::Error> + Send + Sync + 'static;
 
     /// Recursively list all files in the source.
-    async fn list_files(&mut self) -> StdResult<Vec<FileEntry>, Self::Error>;
+    async fn list_files(&self) -> StdResult<Vec<FileEntry>, Self::Error>;
 
-    /// Read a single file and return its contents as bytes.
-    async fn read_file<P: AsRef<Path> + Send>(
-        &mut self,
+    /// Read a single file as a stream of chunks, without holding the whole file in memory.
+    async fn read_stream<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+    ) -> StdResult<FileStream, Self::Error>;
+
+    /// Write a single file from a stream of chunks.
+    ///
+    /// If the stream returns an error, the write fails with that error.
+    async fn write_stream<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        stream: FileStream,
+    ) -> StdResult<(), Self::Error>;
+
+    /// Write a single file from a stream of chunks, storing `attributes` along with it where
+    /// the source can, such as in the same request.
+    ///
+    /// Returns the attributes that were stored. Syncs set the rest afterwards using
+    /// [`FileSource::set_modified`] and [`FileSource::set_permissions`]. By default, this calls [`FileSource::write_stream`] and
+    /// stores nothing.
+    async fn write_stream_with<P: AsRef<Path> + Send>(
+        &self,
         path: P,
-    ) -> StdResult<Vec<u8>, Self::Error>;
+        stream: FileStream,
+        _attributes: FileAttributes,
+    ) -> StdResult<FileAttributes, Self::Error> {
+        self.write_stream(path, stream).await?;
+        Ok(FileAttributes::default())
+    }
+
+    /// Get the entry for a single file, as [`FileSource::list_files`] would list it, or
+    /// `None` if it doesn't exist.
+    ///
+    /// By default, this lists every file and picks out the one at `path`. Sources that can
+    /// look up one file directly should override this.
+    async fn stat_file<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+    ) -> StdResult<Option<FileEntry>, Self::Error> {
+        let path = path.as_ref();
+        let files = self.list_files().await?;
+        Ok(files.into_iter().find(|file| file.path == path))
+    }
+
+    /// Read a single file and return its contents as bytes.
+    ///
+    /// By default, this collects the whole of [`FileSource::read_stream`] into memory.
+    async fn read_file<P: AsRef<Path> + Send>(&self, path: P) -> StdResult<Vec<u8>, Self::Error> {
+        let mut stream = self.read_stream(path).await?;
+        let mut bytes = vec![];
+        while let Some(chunk) = stream.try_next().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
 
     /// Write a single file.
+    ///
+    /// By default, this writes the bytes as a single chunk using [`FileSource::write_stream`].
     async fn write_file<P: AsRef<Path> + Send>(
-        &mut self,
+        &self,
         path: P,
         bytes: &[u8],
-    ) -> StdResult<(), Self::Error>;
+    ) -> StdResult<(), Self::Error> {
+        let chunk = Bytes::copy_from_slice(bytes);
+        self.write_stream(path, Box::pin(stream::once(async { Ok(chunk) })))
+            .await
+    }
+
+    /// Create a symbolic link at `path` pointing to `target`, replacing any file already
+    /// there.
+    ///
+    /// Returns `true` if the link was created. If the source can't store links, this returns
+    /// `false`, and syncs write the target path as the contents of a regular file instead.
+    /// This is the default.
+    async fn write_link<P: AsRef<Path> + Send>(
+        &self,
+        _path: P,
+        _target: &Path,
+    ) -> StdResult<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// Set the permissions, and the owner and group if given, of a single file.
+    ///
+    /// Returns `true` if the permissions were set. By default, this does nothing and returns
+    /// `false`.
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        _path: P,
+        _permissions: FilePermissions,
+    ) -> StdResult<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// Copy the file at `from` to `to` without reading it out of the source, replacing any
+    /// file already at `to`.
+    ///
+    /// Returns `true` if the file was copied. If the source can't copy files itself, this
+    /// returns `false`, and syncs write the file from the other source instead. This is the
+    /// default.
+    async fn copy_file<P: AsRef<Path> + Send>(
+        &self,
+        _from: P,
+        _to: P,
+    ) -> StdResult<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// Move the file at `from` to `to`, replacing any file already at `to`.
+    ///
+    /// Returns `true` if the file was moved. If the source can't move files itself, this
+    /// returns `false`, and syncs write the file from the other source and delete `from`
+    /// instead. This is the default.
+    async fn rename_file<P: AsRef<Path> + Send>(
+        &self,
+        _from: P,
+        _to: P,
+    ) -> StdResult<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// Delete a single file.
+    async fn delete_file<P: AsRef<Path> + Send>(&self, path: P) -> StdResult<(), Self::Error>;
 
     /// Set the modified time, if provided, for a single file.
     ///
     /// Returns `true` if the time was set.
     async fn set_modified<P: AsRef<Path> + Send>(
-        &mut self,
+        &self,
         path: P,
         modified: Option<DateTime<Utc>>,
     ) -> StdResult<bool, Self::Error>;
+
+    /// The algorithm of the [`FileEntry::digest`] this source lists files with, if any.
+    ///
+    /// When [`SyncOptions::verify`] is set, the bytes written to this source are hashed
+    /// with it too, so that the written file's digest can be checked. By default, this
+    /// returns `None`.
+    fn digest_algorithm(&self) -> Option<HashAlgorithm> {
+        None
+    }
+
+    /// Whether an error from this source is likely to go away if the operation is retried,
+    /// such as a timeout or a throttling response. (See [`RetryPolicy`].)
+    ///
+    /// By default, no errors are considered transient.
+    fn is_transient(_error: &Self::Error) -> bool {
+        false
+    }
 }
 
 /// Sync any new or modified files from one [`FileSource`] to another.
@@ -184,55 +481,314 @@ pub trait FileSource {
 /// let config = aws_config::load_from_env().await;
 /// let client = aws_sdk_s3::Client::new(&config);
 ///
-/// let mut local = LocalFiles::new("./my_local_files", true);
-/// let mut s3 = S3Files::new(client, "my_s3_bucket", "path/in/bucket", true);
+/// let local = LocalFiles::new("./my_local_files", true);
+/// let s3 = S3Files::new(client, "my_s3_bucket", "path/in/bucket", true);
 ///
-/// let synced_paths = filesync::sync_one_way(&mut local, &mut s3).await?;
+/// let synced_paths = filesync::sync_one_way(&local, &s3).await?;
 /// assert_eq!(synced_paths, vec![PathBuf::from("my_changed_file.txt")]);
 /// # Ok(())
 /// # }
 /// ```
-pub async fn sync_one_way<A, B>(from: &mut A, to: &mut B) -> Result<Vec<PathBuf>>
+pub async fn sync_one_way<A, B>(from: &A, to: &B) -> Result<Vec<PathBuf>>
 where
     A: FileSource,
     B: FileSource,
 {
-    let destination_files = to
-        .list_files()
-        .await
-        .map_err(SyncError::boxed)?
-        .into_iter()
-        .map(|entry| (entry.path.clone(), entry))
-        .collect::<HashMap<_, _>>();
+    Ok(sync_with_options(from, to, &SyncOptions::default())
+        .await?
+        .written)
+}
 
-    let source_files = from.list_files().await.map_err(SyncError::boxed)?;
+/// Sync files from one [`FileSource`] to another, configured by `options`.
+///
+/// With the default [`SyncOptions`], this is the same as [`sync_one_way`].
+///
+/// The only time `from` is written to is to fix up its modified times, when using
+/// [`ModifiedTimeSync::Both`]. To make sure the source is never changed, use
+/// [`ModifiedTimeSync::DestinationOnly`], and optionally wrap the source in [`ReadOnly`] so
+/// any attempt to write to it is reported as an error.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use filesync::{
+///     local::LocalFiles,
+///     s3::S3Files,
+///     ConflictStrategy, ModifiedTimeSync, SyncOptions,
+/// };
+///
+/// let config = aws_config::load_from_env().await;
+/// let client = aws_sdk_s3::Client::new(&config);
+///
+/// let local = LocalFiles::new("./my_local_files", true);
+/// let s3 = S3Files::new(client, "my_s3_bucket", "path/in/bucket", true);
+///
+/// let options = SyncOptions::builder()
+///     .delete(true)
+///     .resolver(ConflictStrategy::SourceWins)
+///     .modified_time(ModifiedTimeSync::DestinationOnly)
+///     .build();
+///
+/// let report = filesync::sync_with_options(&local, &s3, &options).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn sync_with_options<A, B>(from: &A, to: &B, options: &SyncOptions) -> Result<SyncReport>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    let plan = plan_sync_with(from, to, options).await?;
+    execute_with(from, to, &plan, options).await
+}
 
-    struct Write {
-        path: PathBuf,
-        src_modified: Option<DateTime<Utc>>,
-        dst_modified: Option<DateTime<Utc>>,
+/// Make `to` a mirror of `from`.
+///
+/// This works the same as [`sync_one_way`], except that afterwards any files in `to` which
+/// are missing from `from` are deleted. The deleted paths are returned alongside the written
+/// ones.
+///
+/// Deletes only happen once every write has succeeded.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use filesync::{
+///     local::LocalFiles,
+///     s3::S3Files,
+/// };
+///
+/// let config = aws_config::load_from_env().await;
+/// let client = aws_sdk_s3::Client::new(&config);
+///
+/// let local = LocalFiles::new("./my_site", true);
+/// let s3 = S3Files::new(client, "my_s3_bucket", "site", true);
+///
+/// let report = filesync::sync_mirror(&local, &s3).await?;
+/// println!("Removed {} old files", report.deleted.len());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn sync_mirror<A, B>(from: &A, to: &B) -> Result<SyncReport>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    let options = SyncOptions::builder().delete(true).build();
+    sync_with_options(from, to, &options).await
+}
+
+/// The paths changed in a single [`FileSource`] by a sync.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Files that were created or overwritten, or had only their permissions updated.
+    pub written: Vec<PathBuf>,
+
+    /// Files that were deleted.
+    pub deleted: Vec<PathBuf>,
+
+    /// Files that were left alone because the destination was already up-to-date, or because
+    /// they are missing from the source and deletes are off. Only filled in by one-way syncs.
+    pub skipped: Vec<PathBuf>,
+
+    /// Files that failed to be written or deleted. Only filled in when
+    /// [`SyncOptions::continue_on_error`] is set, as otherwise the first failure is returned
+    /// as an error.
+    pub failed: Vec<FailedFile>,
+}
+
+/// A file that could not be synced, and why.
+///
+/// The error is shared, as [`SyncError`] can't be cloned but reports can.
+#[derive(Debug, Clone)]
+pub struct FailedFile {
+    pub path: PathBuf,
+    pub error: Arc<SyncError>,
+}
+
+/// Two failures are equal if they are for the same path and have the same error message.
+impl PartialEq for FailedFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.error.to_string() == other.error.to_string()
+    }
+}
+
+impl Eq for FailedFile {}
+
+/// The result of a [`sync_two_way`], split by which [`FileSource`] was changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TwoWayReport {
+    /// Changes made to `a`.
+    pub a: SyncReport,
+
+    /// Changes made to `b`.
+    pub b: SyncReport,
+}
+
+/// The entries for a single file on both sides of a [`sync_two_way`], as they were listed
+/// straight after the file was last synced.
+///
+/// Both sides are kept because different sources rarely agree on metadata. For example, an
+/// S3 object will have a different modified time to the local file it was uploaded from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedFile {
+    pub a: FileEntry,
+    pub b: FileEntry,
+}
+
+/// A record of every file as of the last [`sync_two_way`].
+///
+/// This acts as the common ancestor of both sides, so that a change made to one side can be
+/// told apart from a stale copy on the other. It should be persisted between syncs using
+/// [`SyncState::load`] and [`SyncState::save`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    files: BTreeMap<PathBuf, SyncedFile>,
+}
+
+impl SyncState {
+    /// Create an empty `SyncState`, as if the two sources had never been synced.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a `SyncState` from a JSON file.
+    ///
+    /// If the file does not exist, an empty state is returned.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(std::io::Error::from)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(err) => Err(err.into()),
+        }
     }
 
-    let mut to_write: Vec<Write> = vec![];
+    /// Save this `SyncState` to a JSON file.
+    ///
+    /// The file is written beside the old one and renamed over it, so a crash part-way
+    /// through leaves the previous state in place.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let bytes = serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+
+        let temp_path = local::temp_path(path);
+        let result =
+            std::fs::write(&temp_path, bytes).and_then(|()| std::fs::rename(&temp_path, path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        Ok(result?)
+    }
+
+    /// Get the last-synced entries for a single file.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&SyncedFile> {
+        self.files.get(path.as_ref())
+    }
+
+    /// Iterate over every file in the state, sorted by path.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, &SyncedFile)> {
+        self.files.iter().map(|(path, file)| (path.as_path(), file))
+    }
+}
+
+/// Sync changes in both directions between two [`FileSource`]s.
+///
+/// Each side is compared against `state` to find out which files have been created, modified
+/// or deleted since the last sync, and those changes are then copied to the other side.
+///
+/// If a file was changed on both sides, it is treated as a conflict:
+///
+/// 1. If both copies are the same, nothing is written.
+/// 2. If one side was deleted and the other modified, the modified copy is restored.
+/// 3. Otherwise, the copy which is considered more up-to-date wins. (See
+///    [`FileEntry::is_changed_from`].)
+///
+/// With an empty `state`, every file is treated as new, so the first sync will only ever
+/// add or overwrite files and never delete them.
+///
+/// On success, `state` is updated to reflect both sides after the sync.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use filesync::{local::LocalFiles, SyncState};
+///
+/// let laptop = LocalFiles::new("./my_local_files", true);
+/// let backup = LocalFiles::new("./my_backup_files", true);
+///
+/// let mut state = SyncState::load("./sync_state.json")?;
+/// filesync::sync_two_way(&laptop, &backup, &mut state).await?;
+/// state.save("./sync_state.json")?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn sync_two_way<A, B>(a: &A, b: &B, state: &mut SyncState) -> Result<TwoWayReport>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    enum Direction {
+        AToB,
+        BToA,
+    }
+
+    /// A side has changed if a file was added, removed, or differs from the last sync.
+    fn side_changed(current: Option<&FileEntry>, base: Option<&FileEntry>) -> bool {
+        match (current, base) {
+            (Some(current), Some(base)) => !current.is_unchanged_since(base),
+            (None, None) => false,
+            _ => true,
+        }
+    }
+
+    let a_files = list_entries(a).await?;
+    let b_files = list_entries(b).await?;
+
+    let paths = a_files
+        .keys()
+        .chain(b_files.keys())
+        .chain(state.files.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    let mut changes: Vec<(PathBuf, Direction)> = vec![];
     let mut errors: Vec<SyncError> = vec![];
-    for source_file in &source_files {
-        let path = &source_file.path;
-        let matching = destination_files.get(path);
-        match matching {
-            Some(dest_file) => match source_file.is_changed_from(dest_file) {
-                Ok(true) => to_write.push(Write {
-                    path: path.to_owned(),
-                    src_modified: source_file.modified,
-                    dst_modified: dest_file.modified,
-                }),
-                Ok(false) => (),
-                Err(err) => errors.push(err),
-            },
-            None => to_write.push(Write {
-                path: path.to_owned(),
-                src_modified: source_file.modified,
-                dst_modified: None,
-            }),
+    for path in &paths {
+        let base = state.files.get(path);
+        let a_file = a_files.get(path);
+        let b_file = b_files.get(path);
+
+        let a_changed = side_changed(a_file, base.map(|base| &base.a));
+        let b_changed = side_changed(b_file, base.map(|base| &base.b));
+
+        let direction = match (a_changed, b_changed, a_file, b_file) {
+            (false, false, _, _) => None,
+            (true, false, _, _) => Some(Direction::AToB),
+            (false, true, _, _) => Some(Direction::BToA),
+            (true, true, None, None) => None,
+            (true, true, Some(_), None) => Some(Direction::AToB),
+            (true, true, None, Some(_)) => Some(Direction::BToA),
+            (true, true, Some(a_file), Some(b_file)) => {
+                match (
+                    a_file.is_changed_from(b_file),
+                    b_file.is_changed_from(a_file),
+                ) {
+                    (Ok(true), _) => Some(Direction::AToB),
+                    (Ok(false), Ok(true)) => Some(Direction::BToA),
+                    (Ok(false), Ok(false)) => None,
+                    (Err(err), _) | (_, Err(err)) => {
+                        errors.push(err);
+                        None
+                    }
+                }
+            }
+        };
+
+        if let Some(direction) = direction {
+            changes.push((path.to_owned(), direction));
         }
     }
 
@@ -240,22 +796,111 @@ where
         return Err(SyncError::ErrorComparing { errors });
     }
 
-    for write in &to_write {
-        let path = &write.path;
-        let bytes = from.read_file(path).await.map_err(SyncError::boxed)?;
-        to.write_file(path, &bytes)
-            .await
-            .map_err(SyncError::boxed)?;
-        let dest_file_modified_time_updated = to
-            .set_modified(path, write.src_modified)
-            .await
-            .map_err(SyncError::boxed)?;
-        if !dest_file_modified_time_updated {
-            from.set_modified(path, write.dst_modified)
+    let mut report = TwoWayReport::default();
+    for (path, direction) in &changes {
+        match direction {
+            Direction::AToB => match a_files.get(path) {
+                Some(file) => {
+                    copy_file(a, b, file).await?;
+                    report.b.written.push(path.to_owned());
+                }
+                None => {
+                    b.delete_file(path).await.map_err(SyncError::boxed)?;
+                    report.b.deleted.push(path.to_owned());
+                }
+            },
+            Direction::BToA => match b_files.get(path) {
+                Some(file) => {
+                    copy_file(b, a, file).await?;
+                    report.a.written.push(path.to_owned());
+                }
+                None => {
+                    a.delete_file(path).await.map_err(SyncError::boxed)?;
+                    report.a.deleted.push(path.to_owned());
+                }
+            },
+        }
+    }
+
+    // Files that were written need to be listed again to record their new metadata.
+    let (a_synced, b_synced) = match changes.is_empty() {
+        true => (a_files, b_files),
+        false => {
+            let changed = changes
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<BTreeSet<_>>();
+            let mut a_synced = list_entries(a).await?;
+            let mut b_synced = list_entries(b).await?;
+            for (path, entry) in a_files {
+                if !changed.contains(&path) {
+                    a_synced.insert(path, entry);
+                }
+            }
+            for (path, entry) in b_files {
+                if !changed.contains(&path) {
+                    b_synced.insert(path, entry);
+                }
+            }
+            (a_synced, b_synced)
+        }
+    };
+
+    state.files = a_synced
+        .into_iter()
+        .filter_map(|(path, a)| {
+            let b = b_synced.get(&path)?.clone();
+            Some((path, SyncedFile { a, b }))
+        })
+        .collect();
+
+    Ok(report)
+}
+
+pub(crate) async fn list_entries<S: FileSource>(source: &S) -> Result<HashMap<PathBuf, FileEntry>> {
+    Ok(source
+        .list_files()
+        .await
+        .map_err(SyncError::boxed)?
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect())
+}
+
+async fn copy_file<A, B>(from: &A, to: &B, file: &FileEntry) -> Result<()>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    let path = &file.path;
+    let attributes = FileAttributes {
+        modified: file.modified,
+        ..Default::default()
+    };
+    let stored = match &file.link_target {
+        Some(target) => {
+            let linked = to
+                .write_link(path, target)
                 .await
                 .map_err(SyncError::boxed)?;
+            if !linked {
+                to.write_file(path, target.as_os_str().as_encoded_bytes())
+                    .await
+                    .map_err(SyncError::boxed)?;
+            }
+            FileAttributes::default()
+        }
+        None => {
+            let stream = from.read_stream(path).await.map_err(SyncError::boxed)?;
+            to.write_stream_with(path, stream, attributes)
+                .await
+                .map_err(SyncError::boxed)?
         }
+    };
+    if stored.modified.is_none() {
+        to.set_modified(path, file.modified)
+            .await
+            .map_err(SyncError::boxed)?;
     }
-
-    Ok(to_write.into_iter().map(|write| write.path).collect())
+    Ok(())
 }
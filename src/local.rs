@@ -1,12 +1,24 @@
 //! Provides a FileSource for local files on disk.
 
-use std::path::{Path, PathBuf};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
 
 use async_trait::async_trait;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{TryStreamExt, stream};
 use thiserror::Error as ErrorTrait;
 
-use crate::{FileEntry, FileSource};
+use crate::{
+    FileEntry, FilePermissions, FileSource, FileStream, HashAlgorithm,
+    hash::Hasher,
+    hash_cache::{CacheKey, FileHashes, HashCache},
+};
+
+/// The size of each chunk read by [`FileSource::read_stream`].
+const CHUNK_SIZE: usize = 64 * 1024;
 
 /// Error type for `LocalFiles` errors.
 #[derive(Debug, ErrorTrait)]
@@ -18,10 +30,58 @@ pub enum LocalError {
     Io(#[from] std::io::Error),
 }
 
+impl LocalError {
+    /// Whether the error is likely to go away if the operation is retried, such as an
+    /// interrupted read.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            LocalError::Io(error) => crate::retry::io_error_is_transient(error),
+            LocalError::Ignore(_) => false,
+        }
+    }
+}
+
+/// How [`LocalFiles`] treats symbolic links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkMode {
+    /// Leave links out when listing files.
+    #[default]
+    Skip,
+
+    /// List links as if they were the file or directory they point to, so their contents are
+    /// copied. Links that can't be followed make listing fail.
+    Follow,
+
+    /// List links as links, with [`FileEntry::link_target`] set, and recreate links that are
+    /// written to this source with [`FileSource::write_link`]. Links to directories are kept
+    /// as links, and not descended into.
+    Preserve,
+}
+
 /// A [`FileSource`] for local files on disk.
+///
+/// By default, listing files skips hidden files and anything matched by `.gitignore` or
+/// `.ignore` files, the same as [`ignore::WalkBuilder`]. Use [`LocalFiles::include_hidden`]
+/// and [`LocalFiles::git_ignore`] to change that.
+///
+/// File system calls block the thread they run on. With the `tokio` feature enabled, they
+/// are run on Tokio's blocking thread pool when called from inside a Tokio runtime, so that
+/// a sync doesn't hold up other tasks. Otherwise, including when driven by a simple executor
+/// such as `pollster`, they run in place.
+#[derive(Debug, Clone)]
 pub struct LocalFiles {
     root: PathBuf,
     compute_md5_hashes: bool,
+    multipart_md5: Option<(u64, u64)>,
+    include_hidden: bool,
+    git_ignore: bool,
+    ignore_filenames: Vec<String>,
+    symlinks: SymlinkMode,
+    max_depth: Option<usize>,
+    ownership: bool,
+    fsync: bool,
+    hash_cache: Option<PathBuf>,
+    hash_algorithm: Option<HashAlgorithm>,
 }
 
 impl LocalFiles {
@@ -33,61 +93,331 @@ impl LocalFiles {
         LocalFiles {
             root: path.as_ref().into(),
             compute_md5_hashes,
+            multipart_md5: None,
+            include_hidden: false,
+            git_ignore: true,
+            ignore_filenames: vec![],
+            symlinks: SymlinkMode::Skip,
+            max_depth: None,
+            ownership: false,
+            fsync: true,
+            hash_cache: None,
+            hash_algorithm: None,
+        }
+    }
+
+    /// List hidden files and directories, such as `.well-known/`. Defaults to `false`.
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    /// Skip files matched by `.gitignore` files, `.git/info/exclude` and the global git
+    /// ignore file. Defaults to `true`.
+    ///
+    /// As with git, these are only used inside a git repository, which includes any parent
+    /// directory of the root being a repository.
+    pub fn git_ignore(mut self, git_ignore: bool) -> Self {
+        self.git_ignore = git_ignore;
+        self
+    }
+
+    /// Also skip files matched by ignore files called `filename`, such as `.syncignore`.
+    /// These use `.gitignore` syntax, and work outside of git repositories.
+    pub fn ignore_filename<S: AsRef<str>>(mut self, filename: S) -> Self {
+        self.ignore_filenames.push(filename.as_ref().to_owned());
+        self
+    }
+
+    /// Choose how symbolic links are listed and written. Defaults to [`SymlinkMode::Skip`].
+    pub fn symlinks(mut self, symlinks: SymlinkMode) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Only list files up to `max_depth` directories below the root, where `1` means only
+    /// files directly in the root. Defaults to no limit.
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// List each file's owner and group alongside its permissions, and set them when
+    /// permissions are synced to this source. Changing the owner usually needs root.
+    /// Defaults to `false`.
+    ///
+    /// File permissions are only supported on Unix.
+    pub fn ownership(mut self, ownership: bool) -> Self {
+        self.ownership = ownership;
+        self
+    }
+
+    /// Flush each written file to disk before it replaces the old one, and flush its
+    /// directory afterwards, so that a finished write survives a crash or power cut.
+    /// Defaults to `true`.
+    ///
+    /// Files are always written to a temporary file and renamed into place, so readers never
+    /// see a half-written file. Turning this off makes writes faster, but after a crash a
+    /// file may be empty or lost.
+    pub fn fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Store the MD5 hash of each file in a cache at `path`, so that files whose size,
+    /// modified time and inode haven't changed since the last listing aren't hashed again.
+    /// Entries for files that are gone are dropped each time the cache is saved.
+    ///
+    /// The cache is left out of the listing if it is inside the root. Only has an effect if
+    /// `compute_md5_hashes` or [`LocalFiles::hash_algorithm`] is set.
+    pub fn hash_cache<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.hash_cache = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Also compute a [`FileEntry::digest`] of each file using `algorithm` when listing
+    /// files. Defaults to `None`.
+    ///
+    /// Files are only read once, even if their MD5 hashes are computed too. For comparing
+    /// local files quickly, use [`HashAlgorithm::Blake3`].
+    pub fn hash_algorithm(mut self, algorithm: Option<HashAlgorithm>) -> Self {
+        self.hash_algorithm = algorithm;
+        self
+    }
+
+    fn walker(&self) -> ignore::Walk {
+        let mut builder = ignore::WalkBuilder::new(&self.root);
+        builder
+            .hidden(!self.include_hidden)
+            .git_ignore(self.git_ignore)
+            .git_global(self.git_ignore)
+            .git_exclude(self.git_ignore)
+            .follow_links(self.symlinks == SymlinkMode::Follow)
+            .max_depth(self.max_depth)
+            .filter_entry(|entry| !is_temp_file(entry.file_name()));
+        for filename in &self.ignore_filenames {
+            builder.add_custom_ignore_filename(filename);
         }
+        builder.build()
     }
 
-    fn list_files_sync(&mut self) -> Result<Vec<FileEntry>, LocalError> {
+    /// Hash files larger than `threshold` bytes the way S3 calculates the ETag of an object
+    /// uploaded in parts of `part_size` bytes, so they can be compared with the ETags of
+    /// [`S3Files`](crate::s3::S3Files) configured with the same threshold and part size.
+    ///
+    /// Values of `part_size` below [`MIN_PART_SIZE`](crate::MIN_PART_SIZE) are treated as
+    /// `MIN_PART_SIZE`, as they are by `S3Files`. Only has an effect if `compute_md5_hashes`
+    /// is set.
+    pub fn multipart_md5(mut self, threshold: u64, part_size: u64) -> Self {
+        self.multipart_md5 = Some((threshold, part_size.max(crate::MIN_PART_SIZE)));
+        self
+    }
+
+    fn list_files_sync(&self) -> Result<Vec<FileEntry>, LocalError> {
         let mut entries = vec![];
 
-        for entry in ignore::WalkBuilder::new(&self.root).build() {
+        let hashing = self.compute_md5_hashes || self.hash_algorithm.is_some();
+        let mut cache = HashCache::load(self.hash_cache.as_deref().filter(|_| hashing));
+        let cache_path = self
+            .hash_cache
+            .as_ref()
+            .and_then(|path| std::fs::canonicalize(path).ok());
+
+        for entry in self.walker() {
             let entry = entry?;
+            if let Some(cache_path) = &cache_path
+                && entry.file_name() == cache_path.file_name().unwrap_or_default()
+                && std::fs::canonicalize(entry.path()).ok().as_ref() == Some(cache_path)
+            {
+                continue;
+            }
+
+            let path = entry.path().strip_prefix(&self.root).unwrap().to_owned();
             let metadata = entry.metadata()?;
-            if metadata.file_type().is_file() {
-                use std::time::SystemTime;
+            if let Some(file) = self.entry(path, entry.path(), &metadata, &mut cache)? {
+                entries.push(file);
+            }
+        }
 
-                let size = metadata.len();
+        // The cache only saves work on the next listing, so failing to save it doesn't fail
+        // this one.
+        let _ = cache.save();
 
-                let modified = metadata
-                    .modified()
-                    .ok()
-                    .and_then(|system_time| system_time.duration_since(SystemTime::UNIX_EPOCH).ok())
-                    .and_then(|duration| {
-                        NaiveDateTime::from_timestamp_opt(
-                            duration.as_secs() as i64,
-                            duration.subsec_nanos(),
-                        )
-                        .map(|x| x.and_utc())
-                    });
-
-                let md5_hash = match self.compute_md5_hashes {
-                    false => None,
-                    true => Some({
-                        let bytes = std::fs::read(entry.path())?;
-                        let digest = md5::compute(bytes);
-                        u128::from_be_bytes(digest.into())
-                    }),
-                };
+        Ok(entries)
+    }
+
+    fn stat_file_sync(&self, path: &Path) -> Result<Option<FileEntry>, LocalError> {
+        let full_path = self.root.join(path);
+        let metadata = match self.symlinks {
+            SymlinkMode::Follow => std::fs::metadata(&full_path),
+            SymlinkMode::Skip | SymlinkMode::Preserve => std::fs::symlink_metadata(&full_path),
+        };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        // Always hash afresh, as this is used to check files that were just written.
+        let mut cache = HashCache::load(None);
+        self.entry(path.to_owned(), &full_path, &metadata, &mut cache)
+    }
 
-                entries.push(FileEntry {
-                    path: entry.path().strip_prefix(&self.root).unwrap().to_owned(),
+    /// Build the entry for the file at `full_path`, or `None` if it isn't listed.
+    fn entry(
+        &self,
+        path: PathBuf,
+        full_path: &Path,
+        metadata: &std::fs::Metadata,
+        cache: &mut HashCache,
+    ) -> Result<Option<FileEntry>, LocalError> {
+        let file_type = metadata.file_type();
+        let hashing = self.compute_md5_hashes || self.hash_algorithm.is_some();
+
+        let link_target = match self.symlinks {
+            SymlinkMode::Preserve if file_type.is_symlink() => Some(std::fs::read_link(full_path)?),
+            _ if file_type.is_file() => None,
+            _ => return Ok(None),
+        };
+
+        use std::time::SystemTime;
+
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|system_time| system_time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .and_then(|duration| {
+                DateTime::from_timestamp(duration.as_secs() as i64, duration.subsec_nanos())
+            });
+
+        let permissions = match link_target {
+            Some(_) => None,
+            None => self.permissions(metadata),
+        };
+
+        // A link's target is tiny, so it is always hashed.
+        let (size, hashes) = match &link_target {
+            Some(target) => {
+                let bytes = target.as_os_str().as_encoded_bytes();
+                let hashes = FileHashes {
+                    md5_hash: Some(u128::from_be_bytes(md5::compute(bytes).into())),
+                    md5_parts: None,
+                    digest: self.hash_algorithm.map(|algorithm| algorithm.digest(bytes)),
+                };
+                (bytes.len() as u64, hashes)
+            }
+            None if hashing => {
+                let size = metadata.len();
+                let part_size = match self.multipart_md5 {
+                    Some((threshold, part_size)) if size > threshold => Some(part_size),
+                    _ => None,
+                };
+                let key = CacheKey {
+                    size,
                     modified,
-                    size: Some(size),
-                    md5_hash,
-                });
+                    inode: inode(metadata),
+                    md5: self.compute_md5_hashes,
+                    part_size,
+                    algorithm: self.hash_algorithm,
+                };
+                let hashes = cache.hash(&path, key, || {
+                    let md5 = self.compute_md5_hashes.then(|| Md5Hasher::new(part_size));
+                    let hasher = self.hash_algorithm.map(HashAlgorithm::hasher);
+                    hash_file(full_path, md5, hasher)
+                })?;
+                (size, hashes)
             }
+            None => (metadata.len(), FileHashes::default()),
+        };
+
+        Ok(Some(FileEntry {
+            path,
+            modified,
+            size: Some(size),
+            md5_hash: hashes.md5_hash,
+            md5_parts: hashes.md5_parts,
+            link_target,
+            digest: hashes.digest,
+            permissions,
+        }))
+    }
+
+    #[cfg(unix)]
+    fn permissions(&self, metadata: &std::fs::Metadata) -> Option<FilePermissions> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(FilePermissions {
+            mode: metadata.mode() & 0o7777,
+            uid: self.ownership.then(|| metadata.uid()),
+            gid: self.ownership.then(|| metadata.gid()),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn permissions(&self, _metadata: &std::fs::Metadata) -> Option<FilePermissions> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn set_permissions_sync(
+        &self,
+        path: &Path,
+        permissions: FilePermissions,
+    ) -> Result<bool, LocalError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut filepath = self.root.clone();
+        filepath.push(path);
+
+        std::fs::set_permissions(&filepath, std::fs::Permissions::from_mode(permissions.mode))?;
+        if self.ownership && (permissions.uid.is_some() || permissions.gid.is_some()) {
+            std::os::unix::fs::chown(&filepath, permissions.uid, permissions.gid)?;
         }
 
-        Ok(entries)
+        Ok(true)
     }
 
-    fn read_file_sync(&mut self, path: &Path) -> Result<Vec<u8>, LocalError> {
+    #[cfg(not(unix))]
+    fn set_permissions_sync(
+        &self,
+        _path: &Path,
+        _permissions: FilePermissions,
+    ) -> Result<bool, LocalError> {
+        Ok(false)
+    }
+
+    fn read_file_sync(&self, path: &Path) -> Result<Vec<u8>, LocalError> {
         let mut filepath = self.root.clone();
         filepath.push(path);
 
         Ok(std::fs::read(&filepath)?)
     }
 
-    fn write_file_sync(&mut self, path: &Path, bytes: &[u8]) -> Result<(), LocalError> {
+    fn read_stream_sync(&self, path: &Path) -> Result<FileStream, LocalError> {
+        let mut filepath = self.root.clone();
+        filepath.push(path);
+
+        let file = std::fs::File::open(&filepath)?;
+        Ok(Box::pin(stream::try_unfold(file, |mut file| async move {
+            let (buffer, file) = blocking(move || {
+                let mut buffer = vec![0; CHUNK_SIZE];
+                let read = file.read(&mut buffer)?;
+                buffer.truncate(read);
+                Ok::<_, std::io::Error>((buffer, file))
+            })
+            .await?;
+
+            match buffer.is_empty() {
+                true => Ok(None),
+                false => Ok(Some((Bytes::from(buffer), file))),
+            }
+        })))
+    }
+
+    /// Start writing a file to a temporary path next to `path`, creating its directory.
+    ///
+    /// If a file is already at `path`, the new file gets its permissions.
+    fn begin_write(&self, path: &Path) -> Result<PendingWrite, LocalError> {
         let mut filepath = self.root.clone();
         filepath.push(path);
 
@@ -95,11 +425,97 @@ impl LocalFiles {
             std::fs::create_dir_all(path)?;
         }
 
-        Ok(std::fs::write(&filepath, bytes)?)
+        let temp_path = temp_path(&filepath);
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        let pending = PendingWrite {
+            file,
+            temp_path,
+            path: filepath,
+            fsync: self.fsync,
+            committed: false,
+        };
+
+        if let Ok(metadata) = std::fs::symlink_metadata(&pending.path)
+            && metadata.file_type().is_file()
+        {
+            pending.file.set_permissions(metadata.permissions())?;
+        }
+
+        Ok(pending)
+    }
+
+    fn write_file_sync(&self, path: &Path, bytes: &[u8]) -> Result<(), LocalError> {
+        let mut pending = self.begin_write(path)?;
+        pending.file.write_all(bytes)?;
+        Ok(pending.commit()?)
+    }
+
+    fn write_link_sync(&self, path: &Path, target: &Path) -> Result<bool, LocalError> {
+        if self.symlinks != SymlinkMode::Preserve || cfg!(not(any(unix, windows))) {
+            return Ok(false);
+        }
+
+        let mut filepath = self.root.clone();
+        filepath.push(path);
+
+        if let Some(path) = filepath.parent() {
+            std::fs::create_dir_all(path)?;
+        }
+
+        // The link is made beside the file it replaces, then renamed over it, like any other
+        // write.
+        let temp_path = temp_path(&filepath);
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, &temp_path)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(target, &temp_path)?;
+
+        if let Err(error) = std::fs::rename(&temp_path, &filepath) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(error.into());
+        }
+
+        Ok(true)
+    }
+
+    fn copy_file_sync(&self, from: &Path, to: &Path) -> Result<bool, LocalError> {
+        let mut source = std::fs::File::open(self.root.join(from))?;
+        let mut pending = self.begin_write(to)?;
+        std::io::copy(&mut source, &mut pending.file)?;
+        pending.commit()?;
+
+        Ok(true)
+    }
+
+    fn rename_file_sync(&self, from: &Path, to: &Path) -> Result<bool, LocalError> {
+        let to = self.root.join(to);
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(self.root.join(from), &to)?;
+
+        #[cfg(unix)]
+        if self.fsync
+            && let Some(parent) = to.parent()
+        {
+            std::fs::File::open(parent)?.sync_all()?;
+        }
+
+        Ok(true)
+    }
+
+    fn delete_file_sync(&self, path: &Path) -> Result<(), LocalError> {
+        let mut filepath = self.root.clone();
+        filepath.push(path);
+
+        Ok(std::fs::remove_file(&filepath)?)
     }
 
     fn set_modified_sync(
-        &mut self,
+        &self,
         path: &Path,
         modified: Option<DateTime<Utc>>,
     ) -> Result<bool, LocalError> {
@@ -111,7 +527,16 @@ impl LocalFiles {
 
             let time =
                 FileTime::from_unix_time(modified.timestamp(), modified.timestamp_subsec_nanos());
-            filetime::set_file_mtime(&filepath, time)?;
+
+            // When links are preserved, the link's own time is listed, so that is the one set.
+            let metadata = std::fs::symlink_metadata(&filepath)?;
+            match self.symlinks == SymlinkMode::Preserve && metadata.file_type().is_symlink() {
+                true => {
+                    let accessed = FileTime::from_last_access_time(&metadata);
+                    filetime::set_symlink_file_times(&filepath, accessed, time)?;
+                }
+                false => filetime::set_file_mtime(&filepath, time)?,
+            }
 
             Ok(true)
         } else {
@@ -120,32 +545,319 @@ impl LocalFiles {
     }
 }
 
+/// Run blocking file system work on Tokio's blocking thread pool, if the `tokio` feature is
+/// enabled and there is a runtime to use. Otherwise, run it in place.
+async fn blocking<T, E, F>(work: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<std::io::Error> + Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        return runtime
+            .spawn_blocking(work)
+            .await
+            .map_err(std::io::Error::other)?;
+    }
+
+    work()
+}
+
+/// A file being written to a temporary path, which replaces the real path once it is
+/// complete. The temporary file is removed if the write is abandoned.
+struct PendingWrite {
+    file: std::fs::File,
+    temp_path: PathBuf,
+    path: PathBuf,
+    fsync: bool,
+    committed: bool,
+}
+
+impl PendingWrite {
+    /// Move the finished file into place.
+    fn commit(mut self) -> std::io::Result<()> {
+        if self.fsync {
+            self.file.sync_all()?;
+        }
+        std::fs::rename(&self.temp_path, &self.path)?;
+        self.committed = true;
+
+        // The rename itself is only durable once the directory has been flushed too.
+        #[cfg(unix)]
+        if self.fsync
+            && let Some(parent) = self.path.parent()
+        {
+            std::fs::File::open(parent)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// A unique hidden path in the same directory as `path`, so that renaming it over `path`
+/// is atomic.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    use std::hash::{BuildHasher, Hasher};
+
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{:016x}.tmp", name, random))
+}
+
+/// Whether `name` is a file name that [`temp_path`] could have made, so that files which are
+/// still being written, or which were left behind by a crash, aren't listed.
+fn is_temp_file(name: &std::ffi::OsStr) -> bool {
+    let Some(name) = name.to_str() else {
+        return false;
+    };
+    let Some(rest) = name
+        .strip_prefix('.')
+        .and_then(|name| name.strip_suffix(".tmp"))
+    else {
+        return false;
+    };
+    match rest.rsplit_once('.') {
+        Some((name, random)) => {
+            !name.is_empty()
+                && random.len() == 16
+                && random.bytes().all(|byte| byte.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+/// Compute the hashes of a file, reading it once.
+fn hash_file(
+    path: &Path,
+    mut md5: Option<Md5Hasher>,
+    mut hasher: Option<Hasher>,
+) -> Result<FileHashes, LocalError> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        if let Some(md5) = &mut md5 {
+            md5.update(&buffer[..read]);
+        }
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buffer[..read]);
+        }
+    }
+
+    let (md5_hash, md5_parts) = match md5.map(Md5Hasher::finish) {
+        Some((hash, parts)) => (Some(hash), parts),
+        None => (None, None),
+    };
+    Ok(FileHashes {
+        md5_hash,
+        md5_parts,
+        digest: hasher.map(Hasher::finish),
+    })
+}
+
+#[cfg(unix)]
+fn inode(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Computes an MD5 hash a chunk at a time, so large files aren't read into memory.
+///
+/// With a part size, this computes the MD5 hash of the MD5 hashes of each part instead, the
+/// way S3 calculates the ETag of a multipart upload.
+struct Md5Hasher {
+    context: md5::Context,
+    part_size: Option<u64>,
+    part_remaining: u64,
+    part_hashes: Vec<u8>,
+}
+
+impl Md5Hasher {
+    fn new(part_size: Option<u64>) -> Self {
+        Md5Hasher {
+            context: md5::Context::new(),
+            part_size,
+            part_remaining: part_size.unwrap_or(0),
+            part_hashes: vec![],
+        }
+    }
+
+    fn update(&mut self, mut chunk: &[u8]) {
+        let Some(part_size) = self.part_size else {
+            self.context.consume(chunk);
+            return;
+        };
+
+        while !chunk.is_empty() {
+            let len = chunk.len().min(self.part_remaining as usize);
+            self.context.consume(&chunk[..len]);
+            chunk = &chunk[len..];
+            self.part_remaining -= len as u64;
+
+            if self.part_remaining == 0 {
+                let part = std::mem::replace(&mut self.context, md5::Context::new());
+                self.part_hashes.extend_from_slice(&part.compute().0);
+                self.part_remaining = part_size;
+            }
+        }
+    }
+
+    /// Returns the hash, and the number of parts if there is a part size.
+    fn finish(mut self) -> (u128, Option<u32>) {
+        let Some(part_size) = self.part_size else {
+            return (u128::from_be_bytes(self.context.compute().into()), None);
+        };
+
+        if self.part_remaining != part_size {
+            self.part_hashes
+                .extend_from_slice(&self.context.compute().0);
+        }
+
+        let parts = (self.part_hashes.len() / 16) as u32;
+        (
+            u128::from_be_bytes(md5::compute(&self.part_hashes).into()),
+            Some(parts),
+        )
+    }
+}
+
 #[async_trait]
 impl FileSource for LocalFiles {
     type Error = LocalError;
 
-    async fn list_files(&mut self) -> Result<Vec<FileEntry>, Self::Error> {
-        self.list_files_sync()
+    async fn list_files(&self) -> Result<Vec<FileEntry>, Self::Error> {
+        let files = self.clone();
+        blocking(move || files.list_files_sync()).await
+    }
+
+    async fn stat_file<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+    ) -> Result<Option<FileEntry>, Self::Error> {
+        let (files, path) = (self.clone(), path.as_ref().to_owned());
+        blocking(move || files.stat_file_sync(&path)).await
+    }
+
+    async fn read_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>, Self::Error> {
+        let (files, path) = (self.clone(), path.as_ref().to_owned());
+        blocking(move || files.read_file_sync(&path)).await
     }
 
-    async fn read_file<P: AsRef<Path> + Send>(&mut self, path: P) -> Result<Vec<u8>, Self::Error> {
-        self.read_file_sync(path.as_ref())
+    async fn read_stream<P: AsRef<Path> + Send>(&self, path: P) -> Result<FileStream, Self::Error> {
+        let (files, path) = (self.clone(), path.as_ref().to_owned());
+        blocking(move || files.read_stream_sync(&path)).await
+    }
+
+    async fn write_stream<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        mut stream: FileStream,
+    ) -> Result<(), Self::Error> {
+        let (files, path) = (self.clone(), path.as_ref().to_owned());
+        let mut pending = blocking(move || files.begin_write(&path)).await?;
+        while let Some(chunk) = stream.try_next().await? {
+            pending = blocking(move || {
+                pending.file.write_all(&chunk)?;
+                Ok::<_, LocalError>(pending)
+            })
+            .await?;
+        }
+
+        blocking(move || Ok(pending.commit()?)).await
     }
 
     async fn write_file<P: AsRef<Path> + Send>(
-        &mut self,
+        &self,
         path: P,
         bytes: &[u8],
     ) -> Result<(), Self::Error> {
-        self.write_file_sync(path.as_ref(), bytes)
+        let (files, path, bytes) = (self.clone(), path.as_ref().to_owned(), bytes.to_owned());
+        blocking(move || files.write_file_sync(&path, &bytes)).await
+    }
+
+    /// Only supported with [`SymlinkMode::Preserve`].
+    async fn write_link<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        target: &Path,
+    ) -> Result<bool, Self::Error> {
+        let (files, path, target) = (self.clone(), path.as_ref().to_owned(), target.to_owned());
+        blocking(move || files.write_link_sync(&path, &target)).await
+    }
+
+    /// Only supported on Unix. The owner and group are only set if
+    /// [`LocalFiles::ownership`] is set.
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        permissions: FilePermissions,
+    ) -> Result<bool, Self::Error> {
+        let (files, path) = (self.clone(), path.as_ref().to_owned());
+        blocking(move || files.set_permissions_sync(&path, permissions)).await
+    }
+
+    async fn copy_file<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<bool, Self::Error> {
+        let (files, from, to) = (
+            self.clone(),
+            from.as_ref().to_owned(),
+            to.as_ref().to_owned(),
+        );
+        blocking(move || files.copy_file_sync(&from, &to)).await
+    }
+
+    async fn rename_file<P: AsRef<Path> + Send>(
+        &self,
+        from: P,
+        to: P,
+    ) -> Result<bool, Self::Error> {
+        let (files, from, to) = (
+            self.clone(),
+            from.as_ref().to_owned(),
+            to.as_ref().to_owned(),
+        );
+        blocking(move || files.rename_file_sync(&from, &to)).await
+    }
+
+    async fn delete_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<(), Self::Error> {
+        let (files, path) = (self.clone(), path.as_ref().to_owned());
+        blocking(move || files.delete_file_sync(&path)).await
     }
 
     async fn set_modified<P: AsRef<Path> + Send>(
-        &mut self,
+        &self,
         path: P,
         modified: Option<DateTime<Utc>>,
     ) -> Result<bool, Self::Error> {
-        self.set_modified_sync(path.as_ref(), modified)
+        let (files, path) = (self.clone(), path.as_ref().to_owned());
+        blocking(move || files.set_modified_sync(&path, modified)).await
+    }
+
+    fn digest_algorithm(&self) -> Option<HashAlgorithm> {
+        self.hash_algorithm
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        error.is_transient()
     }
 }
 
@@ -155,9 +867,16 @@ mod tests {
 
     #[test]
     fn list_files() {
-        let mut fs = LocalFiles::new("./src", false);
+        let fs = LocalFiles::new("./src", false);
         let files = fs.list_files_sync().unwrap();
-        assert_eq!(files.len(), 4);
+        let paths = files
+            .iter()
+            .map(|file| file.path.as_path())
+            .collect::<Vec<_>>();
+        for path in ["lib.rs", "local.rs", "tests.rs"] {
+            assert!(paths.contains(&Path::new(path)), "{} wasn't listed", path);
+        }
+        assert!(files.iter().all(|file| file.size.is_some()));
     }
 
     #[test]
@@ -172,7 +891,7 @@ mod tests {
         std::fs::create_dir_all(temp).unwrap();
 
         // Create FileSource
-        let mut fs = LocalFiles::new("./temp/local", false);
+        let fs = LocalFiles::new("./temp/local", false);
 
         // Assert emptiness
         assert!(fs.read_file_sync(tempfile).is_err());
@@ -187,4 +906,428 @@ mod tests {
         let bytes = std::fs::read_to_string("./temp/local/tempfile").unwrap();
         assert_eq!(bytes, "Hello");
     }
+
+    #[test]
+    fn stream_roundtrip() {
+        let temp: &Path = "./temp/local_stream".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let fs = LocalFiles::new(temp, false);
+        let contents = (0..CHUNK_SIZE * 2 + 10)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<u8>>();
+
+        let chunks = contents
+            .chunks(1000)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        pollster::block_on(fs.write_stream("nested/file", Box::pin(stream::iter(chunks)))).unwrap();
+
+        let chunks: Vec<Bytes> = pollster::block_on(async {
+            let stream = fs.read_stream("nested/file").await.unwrap();
+            stream.try_collect().await
+        })
+        .unwrap();
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), contents);
+    }
+
+    #[test]
+    fn walk_options() {
+        let temp: &Path = "./temp/local_walk".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let fs = LocalFiles::new(temp, false);
+        for (path, contents) in [
+            ("visible.txt", "visible"),
+            (".well-known/security.txt", "hidden"),
+            (".gitignore", "ignored.txt"),
+            ("ignored.txt", "ignored by git"),
+            (".syncignore", "secret.txt"),
+            ("secret.txt", "ignored by .syncignore"),
+            ("nested/deeper.txt", "deeper"),
+        ] {
+            fs.write_file_sync(path.as_ref(), contents.as_bytes())
+                .unwrap();
+        }
+
+        // `.gitignore` files only apply inside a repository, so don't depend on where the
+        // crate itself is checked out
+        let status = std::process::Command::new("git")
+            .args(["init", "--quiet"])
+            .current_dir(temp)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let list = |fs: LocalFiles| {
+            let mut paths = fs
+                .list_files_sync()
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.path.display().to_string())
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(
+            list(LocalFiles::new(temp, false)),
+            vec!["nested/deeper.txt", "secret.txt", "visible.txt"]
+        );
+        assert_eq!(
+            list(
+                LocalFiles::new(temp, false)
+                    .include_hidden(true)
+                    .git_ignore(false)
+                    .ignore_filename(".syncignore")
+                    .max_depth(Some(1))
+            ),
+            vec![".gitignore", ".syncignore", "ignored.txt", "visible.txt"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_modes() {
+        let temp: &Path = "./temp/local_symlinks".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let source = temp.join("source");
+        let fs = LocalFiles::new(&source, true);
+        fs.write_file_sync("target.txt".as_ref(), b"Hello").unwrap();
+        std::os::unix::fs::symlink("target.txt", source.join("link.txt")).unwrap();
+
+        let list = |fs: LocalFiles| {
+            let mut files = fs.list_files_sync().unwrap();
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            files
+                .into_iter()
+                .map(|entry| {
+                    (
+                        entry.path.display().to_string(),
+                        entry.size,
+                        entry.link_target,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            list(LocalFiles::new(&source, false)),
+            vec![("target.txt".to_owned(), Some(5), None)]
+        );
+        assert_eq!(
+            list(LocalFiles::new(&source, false).symlinks(SymlinkMode::Follow)),
+            vec![
+                ("link.txt".to_owned(), Some(5), None),
+                ("target.txt".to_owned(), Some(5), None),
+            ]
+        );
+        assert_eq!(
+            list(LocalFiles::new(&source, false).symlinks(SymlinkMode::Preserve)),
+            vec![
+                ("link.txt".to_owned(), Some(10), Some("target.txt".into())),
+                ("target.txt".to_owned(), Some(5), None),
+            ]
+        );
+
+        // Links are recreated where they can be, and written as regular files otherwise.
+        let source = LocalFiles::new(&source, true).symlinks(SymlinkMode::Preserve);
+        let linked = LocalFiles::new(temp.join("linked"), true).symlinks(SymlinkMode::Preserve);
+        let unlinked = LocalFiles::new(temp.join("unlinked"), true);
+        std::fs::create_dir_all(temp.join("linked")).unwrap();
+        std::fs::create_dir_all(temp.join("unlinked")).unwrap();
+
+        pollster::block_on(crate::sync_one_way(&source, &linked)).unwrap();
+        pollster::block_on(crate::sync_one_way(&source, &unlinked)).unwrap();
+
+        assert_eq!(
+            std::fs::read_link(temp.join("linked/link.txt")).unwrap(),
+            Path::new("target.txt")
+        );
+        assert_eq!(
+            std::fs::read_to_string(temp.join("unlinked/link.txt")).unwrap(),
+            "target.txt"
+        );
+        assert!(
+            pollster::block_on(crate::sync_one_way(&source, &linked))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permissions_are_synced() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::{SyncActionKind, SyncOptions};
+
+        let temp: &Path = "./temp/local_permissions".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let source = LocalFiles::new(temp.join("source"), true);
+        let destination = LocalFiles::new(temp.join("destination"), true);
+        source
+            .write_file_sync("script.sh".as_ref(), b"#!/bin/sh")
+            .unwrap();
+        destination
+            .write_file_sync("placeholder".as_ref(), b"")
+            .unwrap();
+
+        let mode = |path: &str| {
+            std::fs::metadata(temp.join(path))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        };
+        let set_mode = |path: &str, mode: u32| {
+            std::fs::set_permissions(temp.join(path), std::fs::Permissions::from_mode(mode))
+                .unwrap()
+        };
+        set_mode("source/script.sh", 0o755);
+
+        let options = SyncOptions::builder().permissions(true).build();
+        let sync = || {
+            pollster::block_on(crate::sync_with_options(&source, &destination, &options)).unwrap()
+        };
+
+        sync();
+        assert_eq!(mode("destination/script.sh"), 0o755);
+
+        // A permission-only change is planned and synced on its own
+        set_mode("source/script.sh", 0o700);
+        let plan =
+            pollster::block_on(crate::plan_sync_with(&source, &destination, &options)).unwrap();
+        let kinds = plan
+            .changes()
+            .map(|action| &action.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec![&SyncActionKind::SetPermissions]);
+
+        assert_eq!(sync().written, vec![PathBuf::from("script.sh")]);
+        assert_eq!(mode("destination/script.sh"), 0o700);
+        assert!(sync().written.is_empty());
+
+        // Without the option, permission changes are ignored
+        set_mode("source/script.sh", 0o755);
+        assert!(
+            pollster::block_on(crate::sync_one_way(&source, &destination))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn works_on_a_tokio_runtime() {
+        let temp: &Path = "./temp/local_tokio".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let fs = LocalFiles::new(temp, true);
+        let contents = vec![7; CHUNK_SIZE + 1];
+        fs.write_file("nested/file", &contents).await.unwrap();
+
+        let chunks: Vec<Bytes> = fs
+            .read_stream("nested/file")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(chunks.concat(), contents);
+        assert_eq!(fs.list_files().await.unwrap().len(), 1);
+
+        // The work runs on the blocking pool, not the runtime's own thread
+        let runtime_thread = std::thread::current().id();
+        let work_thread = blocking(|| Ok::<_, std::io::Error>(std::thread::current().id()))
+            .await
+            .unwrap();
+        assert_ne!(work_thread, runtime_thread);
+    }
+
+    #[test]
+    fn runs_in_place_without_a_runtime() {
+        let caller = std::thread::current().id();
+        let work_thread = pollster::block_on(blocking(|| {
+            Ok::<_, std::io::Error>(std::thread::current().id())
+        }))
+        .unwrap();
+        assert_eq!(work_thread, caller);
+    }
+
+    #[test]
+    fn hash_cache_reuses_unchanged_hashes() {
+        let temp: &Path = "./temp/local_hash_cache".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let cache = temp.join("hashes.json");
+        let fs = LocalFiles::new(temp, true).hash_cache(&cache);
+        fs.write_file_sync("a".as_ref(), b"first").unwrap();
+        fs.write_file_sync("b".as_ref(), b"second").unwrap();
+
+        let hashes = |fs: &LocalFiles| {
+            let mut files = fs.list_files_sync().unwrap();
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            files
+                .into_iter()
+                .map(|entry| (entry.path.display().to_string(), entry.md5_hash.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let md5 = |bytes: &[u8]| u128::from_be_bytes(md5::compute(bytes).into());
+
+        // The cache itself is never listed
+        assert_eq!(
+            hashes(&fs),
+            vec![
+                ("a".to_owned(), md5(b"first")),
+                ("b".to_owned(), md5(b"second"))
+            ]
+        );
+
+        // Unchanged files use the stored hash, even if it is wrong. The file is edited as text,
+        // as `serde_json::Value` can't hold 128-bit hashes
+        let stored = std::fs::read_to_string(&cache).unwrap();
+        let stored = stored.replace(&md5(b"first").to_string(), "1");
+        std::fs::write(&cache, stored).unwrap();
+        assert_eq!(
+            hashes(&fs),
+            vec![("a".to_owned(), 1), ("b".to_owned(), md5(b"second"))]
+        );
+
+        // Changed files are hashed again, and deleted ones are pruned
+        fs.write_file_sync("b".as_ref(), b"changed!").unwrap();
+        fs.delete_file_sync("a".as_ref()).unwrap();
+        assert_eq!(hashes(&fs), vec![("b".to_owned(), md5(b"changed!"))]);
+
+        let stored: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&cache).unwrap()).unwrap();
+        let paths = stored["files"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["b"]);
+    }
+
+    #[test]
+    fn hash_cache_failures_dont_fail_listing() {
+        let temp: &Path = "./temp/local_hash_cache_failure".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        // The cache's directory doesn't exist, so it can't be saved
+        let cache = temp.join("missing/hashes.json");
+        let fs = LocalFiles::new(temp, true).hash_cache(&cache);
+        fs.write_file_sync("a".as_ref(), b"first").unwrap();
+
+        let files = fs.list_files_sync().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            files[0].md5_hash,
+            Some(u128::from_be_bytes(md5::compute(b"first").0))
+        );
+        assert!(!cache.exists());
+    }
+
+    #[test]
+    fn failed_writes_leave_the_old_file() {
+        let temp: &Path = "./temp/local_atomic".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let fs = LocalFiles::new(temp, false).fsync(false);
+        fs.write_file_sync("file".as_ref(), b"old").unwrap();
+
+        let chunks = vec![
+            Ok(Bytes::from_static(b"new")),
+            Err(std::io::Error::other("connection lost")),
+        ];
+        let result = pollster::block_on(fs.write_stream("file", Box::pin(stream::iter(chunks))));
+        assert!(result.is_err());
+
+        assert_eq!(fs.read_file_sync("file".as_ref()).unwrap(), b"old");
+        let names = std::fs::read_dir(temp)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["file"]);
+    }
+
+    #[test]
+    fn files_are_copied_and_renamed_in_place() {
+        let temp: &Path = "./temp/local_rename".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let fs = LocalFiles::new(temp, false);
+        fs.write_file_sync("assets/file".as_ref(), b"contents")
+            .unwrap();
+
+        assert!(pollster::block_on(fs.copy_file("assets/file", "copy/file")).unwrap());
+        assert!(pollster::block_on(fs.rename_file("assets/file", "static/assets/file")).unwrap());
+
+        assert!(!temp.join("assets/file").exists());
+        assert_eq!(
+            fs.read_file_sync("copy/file".as_ref()).unwrap(),
+            b"contents"
+        );
+        assert_eq!(
+            fs.read_file_sync("static/assets/file".as_ref()).unwrap(),
+            b"contents"
+        );
+    }
+
+    #[test]
+    fn multipart_md5_matches_s3_etags() {
+        let temp: &Path = "./temp/local_multipart".as_ref();
+        if temp.exists() {
+            std::fs::remove_dir_all(temp).unwrap();
+        }
+
+        let part_size = crate::MIN_PART_SIZE as usize;
+        let fs = LocalFiles::new(temp, true).multipart_md5(100, 100);
+        let contents = (0..part_size * 2 + 500)
+            .map(|i| i as u8)
+            .collect::<Vec<u8>>();
+        fs.write_file_sync("large".as_ref(), &contents).unwrap();
+        fs.write_file_sync("small".as_ref(), &contents[..100])
+            .unwrap();
+
+        // Parts smaller than S3 allows are treated as the minimum part size.
+        let mut part_hashes = vec![];
+        for part in contents.chunks(part_size) {
+            part_hashes.extend_from_slice(&md5::compute(part).0);
+        }
+        let etag = format!("{:x}", md5::compute(&part_hashes));
+
+        let mut files = fs.list_files_sync().unwrap();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(files[0].md5_hash, u128::from_str_radix(&etag, 16).ok());
+        assert_eq!(files[0].md5_parts, Some(3));
+        assert_eq!(
+            files[1].md5_hash,
+            Some(u128::from_be_bytes(md5::compute(&contents[..100]).into()))
+        );
+        assert_eq!(files[1].md5_parts, None);
+    }
 }
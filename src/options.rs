@@ -0,0 +1,265 @@
+//! Configuration for [`sync_with_options`](crate::sync_with_options).
+
+use std::sync::Arc;
+
+use crate::{ConflictResolver, ConflictStrategy, FileFilter, RetryPolicy, SyncEvent, SyncObserver};
+
+/// What to do about modified times after a file has been written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ModifiedTimeSync {
+    /// Set the destination file's modified time to match the source. If the destination
+    /// doesn't support that, set the source file's modified time to match the destination
+    /// instead. This is the default.
+    #[default]
+    Both,
+
+    /// Only ever set the destination file's modified time. With this, a one-way sync never
+    /// writes to its source.
+    DestinationOnly,
+
+    /// Leave modified times alone.
+    Off,
+}
+
+/// Options for a one-way sync. Create one using [`SyncOptions::builder`].
+///
+/// The default options behave the same as [`sync_one_way`](crate::sync_one_way).
+#[derive(Clone)]
+pub struct SyncOptions {
+    delete: bool,
+    resolver: Arc<dyn ConflictResolver>,
+    modified_time: ModifiedTimeSync,
+    max_concurrency: usize,
+    continue_on_error: bool,
+    retry: RetryPolicy,
+    observer: Option<Arc<dyn SyncObserver>>,
+    filter: FileFilter,
+    permissions: bool,
+    verify: bool,
+    detect_renames: bool,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            delete: false,
+            resolver: Arc::new(ConflictStrategy::default()),
+            modified_time: ModifiedTimeSync::default(),
+            max_concurrency: 1,
+            continue_on_error: false,
+            retry: RetryPolicy::default(),
+            observer: None,
+            filter: FileFilter::default(),
+            permissions: false,
+            verify: false,
+            detect_renames: false,
+        }
+    }
+}
+
+impl std::fmt::Debug for SyncOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncOptions")
+            .field("delete", &self.delete)
+            .field("modified_time", &self.modified_time)
+            .field("max_concurrency", &self.max_concurrency)
+            .field("continue_on_error", &self.continue_on_error)
+            .field("retry", &self.retry)
+            .field("filter", &self.filter)
+            .field("permissions", &self.permissions)
+            .field("verify", &self.verify)
+            .field("detect_renames", &self.detect_renames)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SyncOptions {
+    /// Start building a set of options, beginning with the defaults.
+    pub fn builder() -> SyncOptionsBuilder {
+        SyncOptionsBuilder::default()
+    }
+
+    /// Whether files missing from the source are deleted from the destination.
+    pub fn delete(&self) -> bool {
+        self.delete
+    }
+
+    /// The resolver used for files that exist in both sources.
+    pub fn resolver(&self) -> &dyn ConflictResolver {
+        &*self.resolver
+    }
+
+    /// What happens to modified times after a file is written.
+    pub fn modified_time(&self) -> ModifiedTimeSync {
+        self.modified_time
+    }
+
+    /// The maximum number of files transferred at once.
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    /// Whether a file that fails to transfer is recorded in the report instead of stopping the
+    /// sync.
+    pub fn continue_on_error(&self) -> bool {
+        self.continue_on_error
+    }
+
+    /// How operations that fail with a transient error are retried.
+    pub fn retry(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Which files take part in the sync.
+    pub fn filter(&self) -> &FileFilter {
+        &self.filter
+    }
+
+    /// Whether file permissions are copied to the destination.
+    pub fn permissions(&self) -> bool {
+        self.permissions
+    }
+
+    /// Whether each written file is checked against what was read from the source.
+    pub fn verify(&self) -> bool {
+        self.verify
+    }
+
+    /// Whether files moved or copied in the source are moved or copied within the destination
+    /// instead of being written again.
+    pub fn detect_renames(&self) -> bool {
+        self.detect_renames
+    }
+
+    /// The observer that is sent progress events, if any.
+    pub fn observer(&self) -> Option<&dyn SyncObserver> {
+        self.observer.as_deref()
+    }
+
+    pub(crate) fn observer_arc(&self) -> Option<Arc<dyn SyncObserver>> {
+        self.observer.clone()
+    }
+
+    pub(crate) fn notify(&self, event: SyncEvent<'_>) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(event);
+        }
+    }
+}
+
+/// Builder for [`SyncOptions`].
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptionsBuilder {
+    options: SyncOptions,
+}
+
+impl SyncOptionsBuilder {
+    /// Delete files from the destination that are missing from the source, making it a
+    /// mirror. Defaults to `false`.
+    pub fn delete(mut self, delete: bool) -> Self {
+        self.options.delete = delete;
+        self
+    }
+
+    /// Set the [`ConflictResolver`] for files that exist in both sources. Defaults to
+    /// [`ConflictStrategy::NewestWins`].
+    pub fn resolver<R: ConflictResolver + 'static>(mut self, resolver: R) -> Self {
+        self.options.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Set what happens to modified times after a file is written. Defaults to
+    /// [`ModifiedTimeSync::Both`].
+    pub fn modified_time(mut self, modified_time: ModifiedTimeSync) -> Self {
+        self.options.modified_time = modified_time;
+        self
+    }
+
+    /// Set the maximum number of files that can be transferred at once. Values below `1` are
+    /// treated as `1`. Defaults to `1`, meaning files are transferred one after another.
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.options.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// Carry on past files that fail to be written or deleted, recording each failure in
+    /// [`SyncReport::failed`](crate::SyncReport::failed) instead of returning an error.
+    /// Defaults to `false`.
+    ///
+    /// Errors while listing or comparing files still stop the sync before anything is written.
+    pub fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.options.continue_on_error = continue_on_error;
+        self
+    }
+
+    /// Set how operations that fail with a transient error are retried. Defaults to no
+    /// retries.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.options.retry = retry;
+        self
+    }
+
+    /// Only sync files that pass `filter`, on both sides. Defaults to syncing every file.
+    pub fn filter(mut self, filter: FileFilter) -> Self {
+        self.options.filter = filter;
+        self
+    }
+
+    /// Copy each written file's [`FilePermissions`](crate::FilePermissions) to the destination,
+    /// and update the permissions of files whose contents are already in sync but whose
+    /// permissions differ. Defaults to `false`.
+    ///
+    /// Both sources need to support permissions for this to have an effect, such as
+    /// [`LocalFiles`](crate::local::LocalFiles) on Unix, or
+    /// [`S3Files::store_permissions`](crate::s3::S3Files::store_permissions).
+    pub fn permissions(mut self, permissions: bool) -> Self {
+        self.options.permissions = permissions;
+        self
+    }
+
+    /// After writing each file, look it up again in the destination using
+    /// [`FileSource::stat_file`](crate::FileSource::stat_file), and check its size and hash
+    /// against the bytes that were read from the source. Defaults to `false`.
+    ///
+    /// The MD5 hash of the bytes read is always compared if the destination lists plain MD5
+    /// hashes, such as [`LocalFiles`](crate::local::LocalFiles) with `compute_md5_hashes`
+    /// or the ETags of [`S3Files`](crate::s3::S3Files) objects uploaded in one request.
+    /// If the destination lists a [`FileEntry::digest`](crate::FileEntry::digest), the bytes
+    /// read are hashed with its [`FileSource::digest_algorithm`](crate::FileSource::digest_algorithm) too, and
+    /// the digests are compared. Files copied or moved within the destination by
+    /// [`SyncOptionsBuilder::detect_renames`] aren't checked.
+    ///
+    /// A mismatch fails with [`SyncError::VerificationFailed`](crate::SyncError), and is
+    /// retried like a transient error. (See [`SyncOptionsBuilder::retry`].)
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.options.verify = verify;
+        self
+    }
+
+    /// Look for files missing from the destination whose contents match a file that is only
+    /// in the destination, such as after a directory is moved in the source. Defaults to
+    /// `false`.
+    ///
+    /// Matching files are planned as a [`SyncActionKind::Rename`](crate::SyncActionKind) of
+    /// the old file if deletes are on, or a [`SyncActionKind::Copy`](crate::SyncActionKind)
+    /// if they are off, using [`FileSource::rename_file`](crate::FileSource::rename_file) or
+    /// [`FileSource::copy_file`](crate::FileSource::copy_file) on the destination. If the
+    /// destination can't do that, the file is written from the source as usual.
+    ///
+    /// Files are only matched by their size and hash, so both sources need to list hashes
+    /// that can be compared. (See [`FileEntry::is_changed_from`](crate::FileEntry::is_changed_from).)
+    pub fn detect_renames(mut self, detect_renames: bool) -> Self {
+        self.options.detect_renames = detect_renames;
+        self
+    }
+
+    /// Send progress events to `observer` while syncing. Defaults to none.
+    pub fn observer<O: SyncObserver + 'static>(mut self, observer: O) -> Self {
+        self.options.observer = Some(Arc::new(observer));
+        self
+    }
+
+    pub fn build(self) -> SyncOptions {
+        self.options
+    }
+}
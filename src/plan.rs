@@ -0,0 +1,942 @@
+//! Planning a sync ahead of time, so it can be reviewed before any files are touched.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt, stream};
+
+use crate::{
+    FailedFile, FileAttributes, FileEntry, FileSource, HashAlgorithm, ModifiedTimeSync, Resolution,
+    Result, RetryPolicy, SyncError, SyncEvent, SyncOptions, SyncReport,
+    hash::Hasher,
+    resolve::{conflict_path, identical},
+    retry::Failure,
+};
+
+/// The rule that decided whether a file should be written. (See
+/// [`FileEntry::is_changed_from`].)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeReason {
+    /// The file does not exist in the destination.
+    MissingFromDestination,
+
+    /// The file does not exist in the source.
+    MissingFromSource,
+
+    /// The size and hash match, so the files are the same.
+    SameSizeAndHash,
+
+    /// The source was modified more recently than the destination.
+    ModifiedLater,
+
+    /// The source was not modified more recently than the destination.
+    NotModifiedLater,
+
+    /// Without modified times, the size or hash were found to differ.
+    SizeOrHashDiffers,
+
+    /// Without modified times, the size or hash were found to match.
+    SizeOrHashMatches,
+
+    /// The size and modified time match, and there are no hashes to compare, so the files
+    /// are assumed to be the same.
+    SameSizeAndModified,
+
+    /// The source was chosen regardless of the destination. (See
+    /// [`ConflictStrategy::SourceWins`](crate::ConflictStrategy::SourceWins).)
+    SourceWins,
+
+    /// The source is larger than the destination.
+    Larger,
+
+    /// The source is smaller than the destination.
+    NotLarger,
+
+    /// The contents match, but the permissions differ. (See
+    /// [`SyncOptions::permissions`](crate::SyncOptions::permissions).)
+    PermissionsDiffer,
+}
+
+impl ChangeReason {
+    pub(crate) fn size_or_hash(different: bool) -> Self {
+        match different {
+            true => ChangeReason::SizeOrHashDiffers,
+            false => ChangeReason::SizeOrHashMatches,
+        }
+    }
+
+    /// Returns `true` if this reason means the source file should be written.
+    pub fn is_change(self) -> bool {
+        match self {
+            ChangeReason::MissingFromDestination
+            | ChangeReason::ModifiedLater
+            | ChangeReason::SizeOrHashDiffers
+            | ChangeReason::SourceWins
+            | ChangeReason::Larger
+            | ChangeReason::PermissionsDiffer => true,
+            ChangeReason::MissingFromSource
+            | ChangeReason::SameSizeAndHash
+            | ChangeReason::NotModifiedLater
+            | ChangeReason::SizeOrHashMatches
+            | ChangeReason::SameSizeAndModified
+            | ChangeReason::NotLarger => false,
+        }
+    }
+}
+
+/// What will be done to a single file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncActionKind {
+    /// Write a file that is missing from the destination.
+    Create,
+
+    /// Overwrite an outdated file in the destination.
+    Update,
+
+    /// Leave the destination as it is.
+    Skip,
+
+    /// Delete a file that is missing from the source.
+    Delete,
+
+    /// Update the permissions of a file whose contents are already up to date.
+    SetPermissions,
+
+    /// Write a file that is missing from the destination by copying `from`, a file in the
+    /// destination with the same contents. (See
+    /// [`SyncOptionsBuilder::detect_renames`](crate::SyncOptionsBuilder::detect_renames).)
+    Copy { from: PathBuf },
+
+    /// Write a file that is missing from the destination by moving `from`, a file with the
+    /// same contents that is missing from the source, so that it isn't also deleted.
+    Rename { from: PathBuf },
+
+    /// Keep both copies of a conflicting file in the destination. The losing copy is written
+    /// to `conflict_path`, and if the source wins it is then written over the original path.
+    KeepBoth {
+        conflict_path: PathBuf,
+        source_wins: bool,
+    },
+}
+
+/// A single planned change, along with the entries that were compared to decide on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncAction {
+    pub kind: SyncActionKind,
+    pub path: PathBuf,
+    pub source: Option<FileEntry>,
+    pub destination: Option<FileEntry>,
+    pub reason: ChangeReason,
+}
+
+/// Every action that a sync intends to take, as returned by [`plan_sync`].
+///
+/// Nothing is written until the plan is passed to [`execute`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+}
+
+impl SyncPlan {
+    /// Turn every file that only exists in the destination from a skip into a delete.
+    pub fn with_deletes(mut self) -> Self {
+        for action in &mut self.actions {
+            if action.reason == ChangeReason::MissingFromSource {
+                action.kind = SyncActionKind::Delete;
+            }
+        }
+        self
+    }
+
+    /// Iterate over the actions that will change the destination, skipping the rest.
+    pub fn changes(&self) -> impl Iterator<Item = &SyncAction> {
+        self.actions
+            .iter()
+            .filter(|action| action.kind != SyncActionKind::Skip)
+    }
+
+    /// The total size of every file that will be written, including the losing copies of
+    /// files kept by [`SyncActionKind::KeepBoth`]. Files of unknown size count as `0`, as do
+    /// files copied or moved within the destination.
+    pub fn total_bytes(&self) -> u64 {
+        let size = |entry: &Option<FileEntry>| entry.as_ref().and_then(|entry| entry.size);
+
+        self.changes()
+            .map(|action| match &action.kind {
+                SyncActionKind::KeepBoth {
+                    source_wins: true, ..
+                } => size(&action.source).unwrap_or(0) + size(&action.destination).unwrap_or(0),
+                SyncActionKind::Skip
+                | SyncActionKind::Delete
+                | SyncActionKind::SetPermissions
+                | SyncActionKind::Copy { .. }
+                | SyncActionKind::Rename { .. } => 0,
+                _ => size(&action.source).unwrap_or(0),
+            })
+            .sum()
+    }
+
+    /// Returns `true` if executing the plan would not change anything.
+    pub fn is_empty(&self) -> bool {
+        self.changes().next().is_none()
+    }
+}
+
+/// Compare two [`FileSource`]s and work out what [`sync_one_way`](crate::sync_one_way)
+/// would do, without changing anything.
+///
+/// The plan has an action for every file in either source. Files that only exist in `to` are
+/// skipped, unless the plan is turned into a mirror using [`SyncPlan::with_deletes`].
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use filesync::{
+///     local::LocalFiles,
+///     s3::S3Files,
+/// };
+///
+/// let config = aws_config::load_from_env().await;
+/// let client = aws_sdk_s3::Client::new(&config);
+///
+/// let local = LocalFiles::new("./my_local_files", true);
+/// let s3 = S3Files::new(client, "my_s3_bucket", "path/in/bucket", true);
+///
+/// let plan = filesync::plan_sync(&local, &s3).await?;
+/// for action in plan.changes() {
+///     println!("{:?} {} ({:?})", action.kind, action.path.display(), action.reason);
+/// }
+///
+/// filesync::execute(&local, &s3, &plan).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn plan_sync<A, B>(from: &A, to: &B) -> Result<SyncPlan>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    plan_sync_with(from, to, &SyncOptions::default()).await
+}
+
+/// Plan a sync like [`plan_sync`], but configured by `options`.
+///
+/// The options' [`ConflictResolver`](crate::ConflictResolver) decides what happens to files
+/// that exist in both sources, and if deletes are enabled, files only in `to` are planned to
+/// be deleted. Files that don't pass the options' [`FileFilter`](crate::FileFilter) are left
+/// out of the plan entirely.
+///
+/// If [`SyncOptions::detect_renames`] is set, new files are copied or moved from files with
+/// the same contents that are only in `to`, where possible.
+pub async fn plan_sync_with<A, B>(from: &A, to: &B, options: &SyncOptions) -> Result<SyncPlan>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    let resolver = options.resolver();
+
+    options.notify(SyncEvent::ListingStarted);
+
+    let filter = options.filter();
+
+    let mut destination_files = list_files(to, options.retry())
+        .await?
+        .into_iter()
+        .filter(|entry| filter.is_match(&entry.path))
+        .map(|entry| (entry.path.clone(), entry))
+        .collect::<HashMap<_, _>>();
+
+    let mut source_files = list_files(from, options.retry()).await?;
+    source_files.retain(|entry| filter.is_match(&entry.path));
+
+    options.notify(SyncEvent::ListingFinished {
+        source_files: source_files.len(),
+        destination_files: destination_files.len(),
+    });
+
+    let mut actions: Vec<SyncAction> = vec![];
+    let mut errors: Vec<SyncError> = vec![];
+    for source_file in source_files {
+        let matching = destination_files.remove(&source_file.path);
+        let (kind, reason) = match &matching {
+            Some(dest_file) => match resolver.resolve(&source_file, dest_file) {
+                Ok(Resolution::UseSource(reason)) => (SyncActionKind::Update, reason),
+                Ok(Resolution::KeepDestination(_))
+                    if options.permissions()
+                        && permissions_differ(&source_file, dest_file)
+                        && !dest_file.is_changed_from(&source_file).unwrap_or(true) =>
+                {
+                    (
+                        SyncActionKind::SetPermissions,
+                        ChangeReason::PermissionsDiffer,
+                    )
+                }
+                Ok(Resolution::KeepDestination(reason)) => (SyncActionKind::Skip, reason),
+                Ok(Resolution::KeepBoth {
+                    source_wins,
+                    reason,
+                }) => {
+                    let loser = match source_wins {
+                        true => dest_file,
+                        false => &source_file,
+                    };
+                    let conflict_path = conflict_path(&loser.path, loser.modified);
+
+                    // A copy kept by an earlier sync is neither written again nor deleted.
+                    let kept = destination_files
+                        .remove(&conflict_path)
+                        .is_some_and(|kept| identical(loser, &kept).is_some());
+                    let kind = match (kept, source_wins) {
+                        (false, _) => SyncActionKind::KeepBoth {
+                            conflict_path,
+                            source_wins,
+                        },
+                        (true, true) => SyncActionKind::Update,
+                        (true, false) => SyncActionKind::Skip,
+                    };
+                    (kind, reason)
+                }
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            },
+            None => (SyncActionKind::Create, ChangeReason::MissingFromDestination),
+        };
+
+        actions.push(SyncAction {
+            kind,
+            path: source_file.path.clone(),
+            source: Some(source_file),
+            destination: matching,
+            reason,
+        });
+    }
+
+    if !errors.is_empty() {
+        return Err(SyncError::ErrorComparing { errors });
+    }
+
+    let mut orphans = destination_files.into_values().collect::<Vec<_>>();
+    orphans.sort_by(|a, b| a.path.cmp(&b.path));
+    if options.detect_renames() {
+        detect_renames(&mut actions, &mut orphans, options.delete());
+    }
+    actions.extend(orphans.into_iter().map(|dest_file| SyncAction {
+        kind: SyncActionKind::Skip,
+        path: dest_file.path.clone(),
+        source: None,
+        destination: Some(dest_file),
+        reason: ChangeReason::MissingFromSource,
+    }));
+
+    let plan = SyncPlan { actions };
+    let plan = match options.delete() {
+        true => plan.with_deletes(),
+        false => plan,
+    };
+
+    let deletes = plan
+        .changes()
+        .filter(|action| action.kind == SyncActionKind::Delete)
+        .count();
+    options.notify(SyncEvent::Planned {
+        transfers: plan.changes().count() - deletes,
+        deletes,
+        total_bytes: plan.total_bytes(),
+    });
+
+    Ok(plan)
+}
+
+/// Apply a [`SyncPlan`] returned by [`plan_sync`].
+///
+/// Files are created and updated in the order they appear in the plan. Deletes happen only
+/// once every write has succeeded.
+///
+/// If the destination does not support setting the modified time of a written file, the
+/// source file's modified time is set to match the destination instead. (See
+/// [`sync_one_way`](crate::sync_one_way).)
+pub async fn execute<A, B>(from: &A, to: &B, plan: &SyncPlan) -> Result<SyncReport>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    execute_with(from, to, plan, &SyncOptions::default()).await
+}
+
+/// Apply a [`SyncPlan`] like [`execute`], but configured by `options`.
+///
+/// Up to [`SyncOptions::max_concurrency`] files are transferred at once. After the first
+/// error, no more transfers are started, and the error is returned once those already in
+/// flight have finished, so that none are cut off part-way. (An S3 multipart upload that is
+/// dropped part-way can't be aborted, and leaves its parts behind.) If
+/// [`SyncOptions::continue_on_error`] is set instead, every failure is recorded in
+/// [`SyncReport::failed`] and the rest of the plan is carried out.
+pub async fn execute_with<A, B>(
+    from: &A,
+    to: &B,
+    plan: &SyncPlan,
+    options: &SyncOptions,
+) -> Result<SyncReport>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    let retry = options.retry();
+    let max_concurrency = options.max_concurrency();
+
+    let continue_on_error = options.continue_on_error();
+
+    let mut report = SyncReport {
+        skipped: plan
+            .actions
+            .iter()
+            .filter(|action| action.kind == SyncActionKind::Skip)
+            .map(|action| action.path.clone())
+            .collect(),
+        ..SyncReport::default()
+    };
+
+    // Set once a transfer fails, so that transfers which haven't started yet are skipped.
+    let stopped = &AtomicBool::new(false);
+
+    // The futures are collected up front, as closures inside the stream would stop the
+    // returned future from being `Send`.
+    let transfers = plan
+        .changes()
+        .filter(|action| action.kind != SyncActionKind::Delete)
+        .map(|action| async move {
+            if stopped.load(Ordering::SeqCst) {
+                return (action, None);
+            }
+            let result = transfer(from, to, action, options).await;
+            (action, Some(result))
+        })
+        .collect::<Vec<_>>();
+    let mut transfers = stream::iter(transfers).buffered(max_concurrency);
+
+    let mut first_error = None;
+    while let Some((action, result)) = transfers.next().await {
+        let Some(result) = result else {
+            continue;
+        };
+        let path = &action.path;
+        if let Err(error) = &result {
+            options.notify(SyncEvent::Failed { path, error });
+        }
+        match result {
+            Ok(written) => {
+                report.written.extend(written);
+                if let SyncActionKind::Rename { from } = &action.kind {
+                    options.notify(SyncEvent::Deleted { path: from });
+                    report.deleted.push(from.to_owned());
+                }
+            }
+            Err(error) if continue_on_error => report.failed.push(FailedFile {
+                path: path.to_owned(),
+                error: Arc::new(error),
+            }),
+            Err(error) => {
+                stopped.store(true, Ordering::SeqCst);
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    if let Some(error) = first_error {
+        return Err(error);
+    }
+
+    let deletes = plan
+        .changes()
+        .filter(|action| action.kind == SyncActionKind::Delete)
+        .map(|action| &action.path)
+        .collect::<BTreeSet<_>>();
+
+    let deletes = deletes
+        .into_iter()
+        .map(|path| async move {
+            let result = retry
+                .run(|| async {
+                    to.delete_file(path)
+                        .await
+                        .map_err(|error| retry.failure::<B>(error))
+                })
+                .await;
+            (path, result)
+        })
+        .collect::<Vec<_>>();
+    let mut deletes = stream::iter(deletes).buffered(max_concurrency);
+
+    while let Some((path, result)) = deletes.next().await {
+        match &result {
+            Ok(()) => options.notify(SyncEvent::Deleted { path }),
+            Err(error) => options.notify(SyncEvent::Failed { path, error }),
+        }
+        match result {
+            Ok(()) => report.deleted.push(path.to_owned()),
+            Err(error) if continue_on_error => report.failed.push(FailedFile {
+                path: path.to_owned(),
+                error: Arc::new(error),
+            }),
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Turn creates into copies of files that are only in the destination and have the same
+/// size and hash. If `rename` is set, the files are moved instead, so each one is only used
+/// once, and is taken out of `orphans`.
+fn detect_renames(actions: &mut [SyncAction], orphans: &mut Vec<FileEntry>, rename: bool) {
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, orphan) in orphans.iter().enumerate() {
+        if let (Some(size), None) = (orphan.size, &orphan.link_target) {
+            by_size.entry(size).or_default().push(index);
+        }
+    }
+
+    let mut moved = BTreeSet::new();
+    for action in actions {
+        let Some(source) = &action.source else {
+            continue;
+        };
+        if action.kind != SyncActionKind::Create || source.link_target.is_some() {
+            continue;
+        }
+        let Some(candidates) = source.size.and_then(|size| by_size.get_mut(&size)) else {
+            continue;
+        };
+        let Some(position) = candidates
+            .iter()
+            .position(|&index| orphans[index].hash_matches(source) == Some(true))
+        else {
+            continue;
+        };
+
+        let from = orphans[candidates[position]].path.clone();
+        action.kind = match rename {
+            true => {
+                moved.insert(candidates.remove(position));
+                SyncActionKind::Rename { from }
+            }
+            false => SyncActionKind::Copy { from },
+        };
+    }
+
+    *orphans = std::mem::take(orphans)
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !moved.contains(index))
+        .map(|(_, orphan)| orphan)
+        .collect();
+}
+
+/// Whether both files have permissions, and they differ.
+fn permissions_differ(source: &FileEntry, destination: &FileEntry) -> bool {
+    match (source.permissions, destination.permissions) {
+        (Some(source), Some(destination)) => source.differs_from(&destination),
+        _ => false,
+    }
+}
+
+/// List the files in `source`, retrying transient errors.
+async fn list_files<S: FileSource>(source: &S, retry: &RetryPolicy) -> Result<Vec<FileEntry>> {
+    retry
+        .run(|| async {
+            source
+                .list_files()
+                .await
+                .map_err(|error| retry.failure::<S>(error))
+        })
+        .await
+}
+
+/// Copy a single file, retrying transient errors. Each attempt reads the file from the start.
+///
+/// If `entry` is a symbolic link, the link is recreated instead. (See [`write_link`].)
+///
+/// Returns the attributes that `to` stored as part of the write, which don't need setting
+/// again.
+async fn copy<A, B>(
+    from: &A,
+    from_path: &Path,
+    to: &B,
+    to_path: &Path,
+    entry: &Option<FileEntry>,
+    options: &SyncOptions,
+) -> Result<FileAttributes>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    let retry = options.retry();
+    let size = entry.as_ref().and_then(|entry| entry.size);
+    let link_target = entry
+        .as_ref()
+        .and_then(|entry| entry.link_target.as_deref());
+    let attributes = FileAttributes {
+        modified: entry
+            .as_ref()
+            .and_then(|entry| entry.modified)
+            .filter(|_| options.modified_time() != ModifiedTimeSync::Off),
+        permissions: entry
+            .as_ref()
+            .and_then(|entry| entry.permissions)
+            .filter(|_| options.permissions()),
+    };
+
+    let stored = retry
+        .run(|| async {
+            options.notify(SyncEvent::TransferStarted {
+                path: to_path,
+                size,
+            });
+
+            if let Some(target) = link_target {
+                write_link(to, to_path, target, retry).await?;
+                return Ok(FileAttributes::default());
+            }
+
+            let mut stream = from
+                .read_stream(from_path)
+                .await
+                .map_err(|error| retry.failure::<A>(error))?;
+
+            let read = Arc::new(Mutex::new(ReadDigest::new(to.digest_algorithm())));
+            if options.verify() {
+                let read = Arc::clone(&read);
+                stream =
+                    Box::pin(stream.inspect_ok(move |chunk| read.lock().unwrap().update(chunk)));
+            }
+
+            if let Some(observer) = options.observer_arc() {
+                let path = to_path.to_owned();
+                stream = Box::pin(stream.inspect_ok(move |chunk| {
+                    observer.on_event(SyncEvent::BytesTransferred {
+                        path: &path,
+                        bytes: chunk.len() as u64,
+                    })
+                }));
+            }
+
+            let stored = to
+                .write_stream_with(to_path, stream, attributes)
+                .await
+                .map_err(|error| retry.failure::<B>(error))?;
+
+            if options.verify() {
+                let read = std::mem::replace(
+                    &mut *read.lock().unwrap(),
+                    ReadDigest::new(to.digest_algorithm()),
+                );
+                verify(to, to_path, read, retry).await?;
+            }
+            Ok(stored)
+        })
+        .await?;
+
+    options.notify(SyncEvent::TransferFinished { path: to_path });
+    Ok(stored)
+}
+
+/// The size and hashes of the bytes read from the source during a transfer.
+struct ReadDigest {
+    md5: md5::Context,
+    hasher: Option<Hasher>,
+    size: u64,
+}
+
+impl ReadDigest {
+    /// Hash the bytes read with MD5, and with `algorithm`, the algorithm the destination
+    /// lists its digests with.
+    fn new(algorithm: Option<HashAlgorithm>) -> Self {
+        ReadDigest {
+            md5: md5::Context::new(),
+            hasher: algorithm.map(HashAlgorithm::hasher),
+            size: 0,
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        self.md5.consume(chunk);
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(chunk);
+        }
+        self.size += chunk.len() as u64;
+    }
+
+    /// An entry for the bytes read, with the same kinds of hashes the destination lists.
+    fn finish(self, path: &Path) -> FileEntry {
+        FileEntry {
+            path: path.to_owned(),
+            size: Some(self.size),
+            md5_hash: Some(u128::from_be_bytes(self.md5.compute().into())),
+            digest: self.hasher.map(Hasher::finish),
+            ..Default::default()
+        }
+    }
+}
+
+/// Check that the file written to `path` matches what was read from the source.
+async fn verify<S: FileSource>(
+    to: &S,
+    path: &Path,
+    read: ReadDigest,
+    retry: &RetryPolicy,
+) -> std::result::Result<(), Failure> {
+    let written = to
+        .stat_file(path)
+        .await
+        .map_err(|error| retry.failure::<S>(error))?;
+    let read = read.finish(path);
+
+    let mismatch = match written {
+        None => Some("it is missing".to_owned()),
+        Some(written) => match written.size {
+            Some(size) if Some(size) != read.size => Some(format!(
+                "{} bytes were read, but {} were written",
+                read.size.unwrap_or_default(),
+                size
+            )),
+            _ if written.hash_matches(&read) == Some(false) => {
+                Some("its hash doesn't match the bytes read".to_owned())
+            }
+            _ => None,
+        },
+    };
+
+    match mismatch {
+        Some(reason) => Err(Failure::transient(SyncError::VerificationFailed {
+            filename: path.to_owned(),
+            reason,
+        })),
+        None => Ok(()),
+    }
+}
+
+/// Copy or move `original` to `path` within `to`, retrying transient errors.
+///
+/// If `to` can't do that itself, the file is copied from `from` as usual, and when moving,
+/// `original` is then deleted. Returns the attributes stored along with the file.
+async fn copy_within<A, B>(
+    from: &A,
+    to: &B,
+    original: &Path,
+    path: &Path,
+    rename: bool,
+    entry: &Option<FileEntry>,
+    options: &SyncOptions,
+) -> Result<FileAttributes>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    let retry = options.retry();
+
+    if copy_in_place(to, original, path, rename, options).await? {
+        return Ok(FileAttributes::default());
+    }
+
+    let stored = copy(from, path, to, path, entry, options).await?;
+    if rename {
+        retry
+            .run(|| async {
+                to.delete_file(original)
+                    .await
+                    .map_err(|error| retry.failure::<B>(error))
+            })
+            .await?;
+    }
+    Ok(stored)
+}
+
+/// Have `to` copy or move `original` to `path` itself, retrying transient errors. Returns
+/// `false`, having written nothing, if `to` can't.
+async fn copy_in_place<S: FileSource>(
+    to: &S,
+    original: &Path,
+    path: &Path,
+    rename: bool,
+    options: &SyncOptions,
+) -> Result<bool> {
+    let retry = options.retry();
+
+    let done = retry
+        .run(|| async {
+            options.notify(SyncEvent::TransferStarted {
+                path,
+                size: Some(0),
+            });
+            let result = match rename {
+                true => to.rename_file(original, path).await,
+                false => to.copy_file(original, path).await,
+            };
+            result.map_err(|error| retry.failure::<S>(error))
+        })
+        .await?;
+
+    if done {
+        options.notify(SyncEvent::TransferFinished { path });
+    }
+    Ok(done)
+}
+
+/// Create a symbolic link, or a regular file containing the link's target if `to` can't
+/// store links.
+async fn write_link<S: FileSource>(
+    to: &S,
+    path: &Path,
+    target: &Path,
+    retry: &RetryPolicy,
+) -> std::result::Result<(), Failure> {
+    let linked = to
+        .write_link(path, target)
+        .await
+        .map_err(|error| retry.failure::<S>(error))?;
+    if !linked {
+        to.write_file(path, target.as_os_str().as_encoded_bytes())
+            .await
+            .map_err(|error| retry.failure::<S>(error))?;
+    }
+    Ok(())
+}
+
+/// Set the permissions of a single file, if they are known, retrying transient errors.
+async fn set_permissions<S: FileSource>(
+    source: &S,
+    path: &Path,
+    entry: &Option<FileEntry>,
+    retry: &RetryPolicy,
+) -> Result<()> {
+    let Some(permissions) = entry.as_ref().and_then(|entry| entry.permissions) else {
+        return Ok(());
+    };
+
+    retry
+        .run(|| async {
+            source
+                .set_permissions(path, permissions)
+                .await
+                .map_err(|error| retry.failure::<S>(error))
+        })
+        .await?;
+    Ok(())
+}
+
+/// Set the modified time of a single file, retrying transient errors.
+async fn set_modified<S: FileSource>(
+    source: &S,
+    path: &Path,
+    modified: Option<DateTime<Utc>>,
+    retry: &RetryPolicy,
+) -> Result<bool> {
+    retry
+        .run(|| async {
+            source
+                .set_modified(path, modified)
+                .await
+                .map_err(|error| retry.failure::<S>(error))
+        })
+        .await
+}
+
+/// Carry out a single create, update, copy, rename, keep-both or set-permissions action,
+/// returning the paths written.
+async fn transfer<A, B>(
+    from: &A,
+    to: &B,
+    action: &SyncAction,
+    options: &SyncOptions,
+) -> Result<Vec<PathBuf>>
+where
+    A: FileSource,
+    B: FileSource,
+{
+    let modified_time = options.modified_time();
+    let retry = options.retry();
+    let modified = |entry: &Option<FileEntry>| -> Option<DateTime<Utc>> {
+        entry.as_ref().and_then(|entry| entry.modified)
+    };
+
+    let permissions = options.permissions();
+    let mut written = vec![];
+
+    if action.kind == SyncActionKind::SetPermissions {
+        set_permissions(to, &action.path, &action.source, retry).await?;
+        written.push(action.path.to_owned());
+        return Ok(written);
+    }
+
+    if let SyncActionKind::KeepBoth {
+        conflict_path,
+        source_wins,
+    } = &action.kind
+    {
+        let (loser, stored) = match source_wins {
+            true => {
+                // The destination's own copy is moved aside without downloading it, if it can.
+                let loser = &action.destination;
+                let stored =
+                    match copy_in_place(to, &action.path, conflict_path, false, options).await? {
+                        true => FileAttributes::default(),
+                        false => copy(to, &action.path, to, conflict_path, loser, options).await?,
+                    };
+                (loser, stored)
+            }
+            false => {
+                let loser = &action.source;
+                let stored = copy(from, &action.path, to, conflict_path, loser, options).await?;
+                (loser, stored)
+            }
+        };
+        if permissions && stored.permissions.is_none() {
+            set_permissions(to, conflict_path, loser, retry).await?;
+        }
+        if modified_time != ModifiedTimeSync::Off && stored.modified.is_none() {
+            set_modified(to, conflict_path, modified(loser), retry).await?;
+        }
+        written.push(conflict_path.to_owned());
+
+        if !source_wins {
+            return Ok(written);
+        }
+    }
+
+    let path = &action.path;
+    let stored = match &action.kind {
+        SyncActionKind::Copy { from: original } => {
+            copy_within(from, to, original, path, false, &action.source, options).await?
+        }
+        SyncActionKind::Rename { from: original } => {
+            copy_within(from, to, original, path, true, &action.source, options).await?
+        }
+        _ => copy(from, path, to, path, &action.source, options).await?,
+    };
+    written.push(path.to_owned());
+
+    if permissions && stored.permissions.is_none() {
+        set_permissions(to, path, &action.source, retry).await?;
+    }
+
+    if modified_time == ModifiedTimeSync::Off || stored.modified.is_some() {
+        return Ok(written);
+    }
+
+    let dest_file_modified_time_updated =
+        set_modified(to, path, modified(&action.source), retry).await?;
+    if !dest_file_modified_time_updated && modified_time == ModifiedTimeSync::Both {
+        set_modified(from, path, modified(&action.destination), retry).await?;
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,72 @@
+//! Reporting the progress of a sync as it happens.
+
+use std::path::Path;
+
+use crate::SyncError;
+
+/// Something that happened during a sync, passed to a [`SyncObserver`].
+#[derive(Debug, Clone, Copy)]
+pub enum SyncEvent<'a> {
+    /// Both sources are about to be listed.
+    ListingStarted,
+
+    /// Both sources have been listed.
+    ListingFinished {
+        source_files: usize,
+        destination_files: usize,
+    },
+
+    /// The files have been compared, and the sync has been planned.
+    Planned {
+        /// The number of files that will be written.
+        transfers: usize,
+
+        /// The number of files that will be deleted.
+        deletes: usize,
+
+        /// The total size of the files that will be written, as far as it is known.
+        /// (See [`SyncPlan::total_bytes`](crate::SyncPlan::total_bytes).)
+        total_bytes: u64,
+    },
+
+    /// A file has started being written. If a transfer is retried, this is sent again and
+    /// the file's bytes are counted again from the start.
+    ///
+    /// Files copied or moved within the destination have a size of `0`, as no bytes are
+    /// transferred.
+    TransferStarted { path: &'a Path, size: Option<u64> },
+
+    /// Another chunk of a file has been read, ready to be written.
+    BytesTransferred { path: &'a Path, bytes: u64 },
+
+    /// A file has been written.
+    TransferFinished { path: &'a Path },
+
+    /// A file has been deleted.
+    Deleted { path: &'a Path },
+
+    /// A file failed to be written or deleted.
+    Failed {
+        path: &'a Path,
+        error: &'a SyncError,
+    },
+}
+
+/// Receives [`SyncEvent`]s while a sync runs, for example to drive a progress bar.
+///
+/// Events can arrive from several transfers at once if
+/// [`SyncOptions::max_concurrency`](crate::SyncOptions::max_concurrency) is above `1`, so
+/// this should return quickly. Any closure taking a `SyncEvent` is an observer, which makes
+/// it easy to forward events to a channel.
+pub trait SyncObserver: Send + Sync {
+    fn on_event(&self, event: SyncEvent<'_>);
+}
+
+impl<F> SyncObserver for F
+where
+    F: Fn(SyncEvent<'_>) + Send + Sync,
+{
+    fn on_event(&self, event: SyncEvent<'_>) {
+        self(event)
+    }
+}
@@ -0,0 +1,142 @@
+//! A wrapper that stops a [`FileSource`] from being written to.
+
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use thiserror::Error as ErrorTrait;
+
+use crate::{FileEntry, FilePermissions, FileSource, FileStream, HashAlgorithm};
+
+/// Error type for [`ReadOnly`] sources.
+#[derive(Debug, ErrorTrait)]
+pub enum ReadOnlyError<E: std::error::Error + 'static> {
+    #[error("Attempted to {operation} read-only file {path}")]
+    WriteAttempted {
+        operation: &'static str,
+        path: PathBuf,
+    },
+
+    #[error(transparent)]
+    Source(E),
+}
+
+impl<E> From<std::io::Error> for ReadOnlyError<E>
+where
+    E: std::error::Error + From<std::io::Error> + 'static,
+{
+    fn from(error: std::io::Error) -> Self {
+        ReadOnlyError::Source(E::from(error))
+    }
+}
+
+/// A [`FileSource`] that can be listed and read, but fails with
+/// [`ReadOnlyError::WriteAttempted`] instead of writing, deleting, or setting modified times.
+///
+/// Useful for making sure a sync never changes its source, for example a read-only mount or
+/// files owned by someone else. A one-way sync only writes to its source when
+/// [`ModifiedTimeSync::Both`](crate::ModifiedTimeSync::Both) is used and the destination
+/// can't store modified times, so use [`ModifiedTimeSync::DestinationOnly`](crate::ModifiedTimeSync::DestinationOnly)
+/// alongside this to avoid errors.
+pub struct ReadOnly<S>(pub S);
+
+impl<S: FileSource> ReadOnly<S> {
+    fn write_attempted(operation: &'static str, path: &Path) -> ReadOnlyError<S::Error> {
+        ReadOnlyError::WriteAttempted {
+            operation,
+            path: path.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: FileSource> FileSource for ReadOnly<S> {
+    type Error = ReadOnlyError<S::Error>;
+
+    async fn list_files(&self) -> Result<Vec<FileEntry>, Self::Error> {
+        self.0.list_files().await.map_err(ReadOnlyError::Source)
+    }
+
+    async fn stat_file<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+    ) -> Result<Option<FileEntry>, Self::Error> {
+        self.0.stat_file(path).await.map_err(ReadOnlyError::Source)
+    }
+
+    async fn read_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>, Self::Error> {
+        self.0.read_file(path).await.map_err(ReadOnlyError::Source)
+    }
+
+    async fn read_stream<P: AsRef<Path> + Send>(&self, path: P) -> Result<FileStream, Self::Error> {
+        self.0
+            .read_stream(path)
+            .await
+            .map_err(ReadOnlyError::Source)
+    }
+
+    async fn write_stream<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        _stream: FileStream,
+    ) -> Result<(), Self::Error> {
+        Err(Self::write_attempted("write", path.as_ref()))
+    }
+
+    async fn write_link<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        _target: &Path,
+    ) -> Result<bool, Self::Error> {
+        Err(Self::write_attempted("link", path.as_ref()))
+    }
+
+    async fn copy_file<P: AsRef<Path> + Send>(&self, _from: P, to: P) -> Result<bool, Self::Error> {
+        Err(Self::write_attempted("copy to", to.as_ref()))
+    }
+
+    async fn rename_file<P: AsRef<Path> + Send>(
+        &self,
+        from: P,
+        _to: P,
+    ) -> Result<bool, Self::Error> {
+        Err(Self::write_attempted("rename", from.as_ref()))
+    }
+
+    async fn delete_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<(), Self::Error> {
+        Err(Self::write_attempted("delete", path.as_ref()))
+    }
+
+    async fn set_modified<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        _modified: Option<DateTime<Utc>>,
+    ) -> Result<bool, Self::Error> {
+        Err(Self::write_attempted(
+            "set the modified time of",
+            path.as_ref(),
+        ))
+    }
+
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        _permissions: FilePermissions,
+    ) -> Result<bool, Self::Error> {
+        Err(Self::write_attempted(
+            "set the permissions of",
+            path.as_ref(),
+        ))
+    }
+
+    fn digest_algorithm(&self) -> Option<HashAlgorithm> {
+        self.0.digest_algorithm()
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        match error {
+            ReadOnlyError::Source(error) => S::is_transient(error),
+            ReadOnlyError::WriteAttempted { .. } => false,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Deciding what to do when a file exists in both sources and differs.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::{ChangeReason, FileEntry, Result, SyncError};
+
+/// What to do with a file that exists in both the source and the destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Overwrite the destination with the source.
+    UseSource(ChangeReason),
+
+    /// Leave the destination as it is.
+    KeepDestination(ChangeReason),
+
+    /// Keep both copies in the destination. The losing copy is written alongside the winner
+    /// with a `.conflict-<timestamp>` suffix. (See [`conflict_path`].)
+    ///
+    /// If the destination already has the same losing copy at that path, it isn't written
+    /// again, and it isn't deleted as missing from the source.
+    KeepBoth {
+        source_wins: bool,
+        reason: ChangeReason,
+    },
+}
+
+/// Decides which copy of a file wins when it exists in both sources.
+///
+/// This is called for every file present in both the source and the destination, including
+/// ones that are identical. Returning an error stops the sync before anything is written.
+pub trait ConflictResolver: Send + Sync {
+    fn resolve(&self, source: &FileEntry, destination: &FileEntry) -> Result<Resolution>;
+}
+
+/// The built-in [`ConflictResolver`]s.
+///
+/// Apart from [`ConflictStrategy::NewestWins`], each of these first checks whether the files
+/// look identical and leaves the destination alone if so.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictStrategy {
+    /// Always overwrite the destination, even if it looks more up-to-date.
+    SourceWins,
+
+    /// Use the rules of [`FileEntry::is_changed_from`]. This is the default.
+    #[default]
+    NewestWins,
+
+    /// The larger file wins. Files of the same size fall back to `NewestWins`.
+    LargerWins,
+
+    /// Keep both copies, renaming whichever one `NewestWins` would discard.
+    KeepBoth,
+
+    /// Fail the sync if any file differs.
+    Fail,
+}
+
+impl ConflictResolver for ConflictStrategy {
+    fn resolve(&self, source: &FileEntry, destination: &FileEntry) -> Result<Resolution> {
+        if *self != ConflictStrategy::NewestWins
+            && let Some(reason) = identical(source, destination)
+        {
+            return Ok(Resolution::KeepDestination(reason));
+        }
+
+        match self {
+            ConflictStrategy::SourceWins => Ok(Resolution::UseSource(ChangeReason::SourceWins)),
+            ConflictStrategy::NewestWins => newest_wins(source, destination),
+            ConflictStrategy::LargerWins => match (source.size, destination.size) {
+                (Some(a), Some(b)) if a > b => Ok(Resolution::UseSource(ChangeReason::Larger)),
+                (Some(a), Some(b)) if a < b => {
+                    Ok(Resolution::KeepDestination(ChangeReason::NotLarger))
+                }
+                _ => newest_wins(source, destination),
+            },
+            ConflictStrategy::KeepBoth => {
+                let reason = source.compare(destination)?;
+                Ok(Resolution::KeepBoth {
+                    source_wins: reason.is_change(),
+                    reason,
+                })
+            }
+            ConflictStrategy::Fail => Err(SyncError::Conflict {
+                filename: source.path.clone(),
+            }),
+        }
+    }
+}
+
+fn newest_wins(source: &FileEntry, destination: &FileEntry) -> Result<Resolution> {
+    let reason = source.compare(destination)?;
+    Ok(match reason.is_change() {
+        true => Resolution::UseSource(reason),
+        false => Resolution::KeepDestination(reason),
+    })
+}
+
+/// Returns a reason if the two files can be shown to be the same.
+///
+/// Matching sizes and modified times only count if there are no hashes to compare.
+pub(crate) fn identical(source: &FileEntry, destination: &FileEntry) -> Option<ChangeReason> {
+    if let Ok(reason @ (ChangeReason::SameSizeAndHash | ChangeReason::SizeOrHashMatches)) =
+        source.compare(destination)
+    {
+        return Some(reason);
+    }
+    if source.hash_matches(destination).is_some() {
+        return None;
+    }
+
+    let same_size = source.size.is_some() && source.size == destination.size;
+    let same_modified = source.modified.is_some() && source.modified == destination.modified;
+    (same_size && same_modified).then_some(ChangeReason::SameSizeAndModified)
+}
+
+/// The path that the losing copy of a conflicting file is kept at, for example
+/// `notes.txt.conflict-20240131T120000Z`.
+///
+/// The timestamp is the modified time of the losing copy, or the current time if it has none.
+pub fn conflict_path(path: &Path, modified: Option<DateTime<Utc>>) -> PathBuf {
+    let timestamp = modified.unwrap_or_else(Utc::now).format("%Y%m%dT%H%M%SZ");
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".conflict-{}", timestamp));
+    name.into()
+}
@@ -0,0 +1,373 @@
+//! Retrying operations that fail with transient errors.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, hash_map::RandomState},
+    error::Error,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, OnceLock},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{FileSource, Result, SyncError};
+
+type Classifier = dyn Fn(&(dyn Error + 'static)) -> bool + Send + Sync;
+
+/// How a sync retries operations that fail with a transient error, such as a dropped
+/// connection or a throttling response.
+///
+/// Each listing, file transfer, delete and modified time update is retried on its own. The
+/// delay before each retry doubles, starting at the base delay, up to the maximum delay.
+///
+/// By default, nothing is retried.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    classifier: Option<Arc<Classifier>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(1)
+    }
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_delay", &self.base_delay)
+            .field("max_delay", &self.max_delay)
+            .field("jitter", &self.jitter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RetryPolicy {
+    /// Create a policy that makes up to `max_attempts` attempts at each operation, including
+    /// the first. Values below `1` are treated as `1`.
+    ///
+    /// The base delay defaults to 100ms, the maximum delay to 10s, and jitter is on.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            classifier: None,
+        }
+    }
+
+    /// Set the delay before the first retry.
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Set the longest delay between two attempts.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Randomly shorten each delay by up to half, so that many failed operations don't all
+    /// retry at the same moment.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Decide which errors are retried using `classifier`, instead of
+    /// [`FileSource::is_transient`]. The error is the source's own error type, so it can be
+    /// downcast to check for specific errors.
+    pub fn retry_if<F>(mut self, classifier: F) -> Self
+    where
+        F: Fn(&(dyn Error + 'static)) -> bool + Send + Sync + 'static,
+    {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// The maximum number of attempts made at each operation.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// The delay before the given retry, where the first retry is `1`, ignoring jitter.
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        match self.jitter {
+            true => {
+                let random = RandomState::new().build_hasher().finish();
+                backoff.mul_f64(0.5 + (random as f64 / u64::MAX as f64) / 2.0)
+            }
+            false => backoff,
+        }
+    }
+
+    /// Box up an error from `S`, remembering whether it is worth retrying.
+    pub(crate) fn failure<S: FileSource>(&self, error: S::Error) -> Failure {
+        let transient = match &self.classifier {
+            Some(classifier) => classifier(&error),
+            None => S::is_transient(&error),
+        };
+
+        Failure {
+            error: SyncError::boxed(error),
+            transient,
+        }
+    }
+
+    /// Run `operation` until it succeeds, fails with an error that isn't transient, or runs
+    /// out of attempts.
+    pub(crate) async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, Failure>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(failure) if failure.transient && attempt < self.max_attempts => {
+                    sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                Err(failure) => return Err(failure.error),
+            }
+        }
+    }
+}
+
+/// An error from a single attempt at an operation.
+pub(crate) struct Failure {
+    error: SyncError,
+    transient: bool,
+}
+
+impl Failure {
+    /// A failure that is always worth retrying.
+    pub(crate) fn transient(error: SyncError) -> Self {
+        Failure {
+            error,
+            transient: true,
+        }
+    }
+}
+
+/// Whether an I/O error is likely to go away if the operation is tried again.
+pub(crate) fn io_error_is_transient(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    matches!(
+        error.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+    )
+}
+
+/// Wait for `duration`.
+///
+/// This doesn't depend on any async runtime: every sleep is queued on a single shared timer
+/// thread that wakes it when its deadline passes. Tokio's timer isn't used even when running on
+/// Tokio, as it panics on runtimes built without their time driver.
+async fn sleep(duration: Duration) {
+    Timer::shared().sleep(duration).await
+}
+
+/// The timer thread shared by every sleep.
+struct Timer {
+    queue: Mutex<BinaryHeap<Reverse<Deadline>>>,
+    changed: Condvar,
+}
+
+impl Timer {
+    fn shared() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+
+        let mut created = false;
+        let timer = TIMER.get_or_init(|| {
+            created = true;
+            Timer {
+                queue: Mutex::new(BinaryHeap::new()),
+                changed: Condvar::new(),
+            }
+        });
+        if created {
+            std::thread::Builder::new()
+                .name("filesync-timer".into())
+                .spawn(move || timer.run())
+                .expect("failed to spawn the retry timer thread");
+        }
+        timer
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let state = Arc::new(Mutex::new(SleepState::default()));
+
+        let mut queue = self.queue.lock().unwrap();
+        queue.push(Reverse(Deadline {
+            at: Instant::now() + duration,
+            state: Arc::clone(&state),
+        }));
+        self.changed.notify_one();
+
+        Sleep { state }
+    }
+
+    fn run(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            match queue.peek() {
+                None => queue = self.changed.wait(queue).unwrap(),
+                Some(Reverse(next)) if next.at <= now => {
+                    let Reverse(next) = queue.pop().unwrap();
+                    let mut state = next.state.lock().unwrap();
+                    state.done = true;
+                    if let Some(waker) = state.waker.take() {
+                        waker.wake();
+                    }
+                }
+                Some(Reverse(next)) => {
+                    let wait = next.at - now;
+                    queue = self.changed.wait_timeout(queue, wait).unwrap().0;
+                }
+            }
+        }
+    }
+}
+
+/// A pending sleep, ordered by when it's due.
+struct Deadline {
+    at: Instant,
+    state: Arc<Mutex<SleepState>>,
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deadline {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.at.cmp(&other.at)
+    }
+}
+
+#[derive(Default)]
+struct SleepState {
+    done: bool,
+    waker: Option<Waker>,
+}
+
+struct Sleep {
+    state: Arc<Mutex<SleepState>>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.done {
+            true => Poll::Ready(()),
+            false => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay() {
+        let policy = RetryPolicy::new(10)
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(500));
+
+        let delays = (1..=5)
+            .map(|retry| policy.backoff(retry))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 500, 500]
+                .map(Duration::from_millis)
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn jitter_shortens_delays_by_up_to_half() {
+        let policy = RetryPolicy::new(10).base_delay(Duration::from_millis(100));
+
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+        assert_eq!(policy.jitter(false).delay(1), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn sleep_works_without_a_runtime() {
+        let start = Instant::now();
+        pollster::block_on(sleep(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn sleeps_share_one_timer_thread() {
+        let start = Instant::now();
+        let sleeps = [60, 20, 40].map(|millis| sleep(Duration::from_millis(millis)));
+        pollster::block_on(futures::future::join_all(sleeps));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(60) && elapsed < Duration::from_millis(120));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn sleep_works_on_a_tokio_runtime() {
+        let start = Instant::now();
+        sleep(Duration::from_millis(20)).await;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn sleep_works_on_a_tokio_runtime_without_timers() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        runtime.block_on(sleep(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -1,13 +1,64 @@
 //! Provides a FileSource for a path in an S3 bucket.
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use async_trait::async_trait;
-use aws_sdk_s3::Client;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use aws_sdk_s3::{
+    Client,
+    error::SdkError,
+    operation::head_object::HeadObjectOutput,
+    primitives::ByteStream,
+    types::{
+        ChecksumAlgorithm, ChecksumMode, CompletedMultipartUpload, CompletedPart, MetadataDirective,
+    },
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{
+    StreamExt, TryStreamExt,
+    future::{self, Either},
+    stream::{self, FuturesUnordered},
+};
 use thiserror::Error as ErrorTrait;
 
-use crate::{FileEntry, FileSource};
+use crate::{
+    Digest, FileAttributes, FileEntry, FilePermissions, FileSource, FileStream, HashAlgorithm,
+};
+
+pub use crate::MIN_PART_SIZE;
+
+/// The most parts S3 accepts in a single multipart upload.
+pub const MAX_PARTS: i32 = 10_000;
+
+/// The default size above which files are uploaded in parts.
+pub const DEFAULT_MULTIPART_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+/// The default size of each part of a multipart upload.
+pub const DEFAULT_PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The largest object that can be copied with a single `copy_object` call.
+const MAX_COPY_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+
+/// The user metadata key that modified times are stored under, as `x-amz-meta-mtime`.
+const MTIME_METADATA: &str = "mtime";
+
+/// The user metadata key that symbolic link targets are stored under, as
+/// `x-amz-meta-symlink-target`.
+const LINK_TARGET_METADATA: &str = "symlink-target";
+
+/// The user metadata keys that permissions are stored under: the mode in octal, and the
+/// owner and group IDs in decimal.
+const MODE_METADATA: &str = "mode";
+const UID_METADATA: &str = "uid";
+const GID_METADATA: &str = "gid";
+
+/// How many objects are inspected at once when listing needs more than `list_objects_v2`
+/// returns.
+const HEAD_CONCURRENCY: usize = 16;
 
 /// Error type for `S3Files` errors.
 #[derive(Debug, ErrorTrait)]
@@ -18,11 +69,86 @@ pub enum S3Error {
     #[error("One of the objects returned has an incorrect prefix")]
     ObjectWrongPrefix,
 
+    #[error("S3 did not return an upload ID for a multipart upload")]
+    MissingUploadId,
+
+    #[error("The file is too large to upload in {MAX_PARTS} parts of {part_size} bytes")]
+    TooManyParts { part_size: u64 },
+
     #[error(transparent)]
     ByteStreamError(#[from] aws_sdk_s3::primitives::ByteStreamError),
 
+    /// A request to S3 failed. `status` is the HTTP status of the response, if one was
+    /// received, and `connection_failed` is set when the request timed out or couldn't be
+    /// sent.
+    #[error("{source}")]
+    S3Error {
+        source: aws_sdk_s3::Error,
+        status: Option<u16>,
+        connection_failed: bool,
+    },
+
     #[error(transparent)]
-    S3Error(#[from] aws_sdk_s3::Error),
+    Io(#[from] std::io::Error),
+}
+
+/// S3 error codes that mean the request can be tried again.
+const TRANSIENT_ERROR_CODES: &[&str] = &[
+    "InternalError",
+    "RequestTimeout",
+    "RequestTimeTooSkewed",
+    "ServiceUnavailable",
+    "SlowDown",
+    "Throttling",
+    "ThrottlingException",
+];
+
+impl S3Error {
+    /// Whether the error is likely to go away if the request is retried, such as a throttling
+    /// response, a server error, or a dropped connection.
+    ///
+    /// This is decided from the HTTP status where there is one: 5xx, 429 and 408 responses are
+    /// transient, as are the error codes S3 uses for them. Other responses, such as a `403` or
+    /// `404` to a `HEAD` request that has no error code, are not.
+    pub fn is_transient(&self) -> bool {
+        use aws_sdk_s3::error::ProvideErrorMetadata;
+
+        match self {
+            S3Error::S3Error {
+                source,
+                status,
+                connection_failed,
+            } => {
+                *connection_failed
+                    || matches!(status, Some(500..=599 | 429 | 408))
+                    || matches!(source, aws_sdk_s3::Error::Unhandled(error)
+                        if error.code().is_some_and(|code| TRANSIENT_ERROR_CODES.contains(&code)))
+            }
+            S3Error::ByteStreamError(_) => true,
+            S3Error::Io(error) => crate::retry::io_error_is_transient(error),
+            _ => false,
+        }
+    }
+
+    /// Converts an error from the S3 client, keeping what it says about the response.
+    fn from_sdk<E, B>(error: SdkError<E, http::Response<B>>) -> Self
+    where
+        aws_sdk_s3::Error: From<SdkError<E, http::Response<B>>>,
+    {
+        let status = error
+            .raw_response()
+            .map(|response| response.status().as_u16());
+        let connection_failed = match &error {
+            SdkError::TimeoutError(_) => true,
+            SdkError::DispatchFailure(failure) => !failure.is_user(),
+            _ => false,
+        };
+        S3Error::S3Error {
+            source: error.into(),
+            status,
+            connection_failed,
+        }
+    }
 }
 
 /// A [`FileSource`] for files under a path in an S3 bucket.
@@ -33,13 +159,21 @@ pub struct S3Files {
     bucket: String,
     prefix: PathBuf,
     use_etag_as_hash: bool,
+    multipart_threshold: u64,
+    part_size: u64,
+    max_parallel_parts: usize,
+    store_modified_times: bool,
+    store_symlinks: bool,
+    store_permissions: bool,
+    checksum_algorithm: Option<HashAlgorithm>,
 }
 
 impl S3Files {
     /// Create a new `S3Files` for a path in an S3 bucket.
     ///
     /// If the `use_etag_as_hash` flag is set, the ETag of each S3 object will be assumed to
-    /// be an MD5 hash of the contents (if it is a 128 hex value).
+    /// be an MD5 hash of the contents (if it is a 128 hex value). ETags of objects uploaded in
+    /// parts are recognised, and their part count is stored in [`FileEntry::md5_parts`].
     pub fn new<S: AsRef<str>, P: AsRef<Path>>(
         client: Client,
         bucket: S,
@@ -51,30 +185,481 @@ impl S3Files {
             bucket: bucket.as_ref().to_owned(),
             prefix: prefix.as_ref().to_owned(),
             use_etag_as_hash,
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            part_size: DEFAULT_PART_SIZE,
+            max_parallel_parts: 4,
+            store_modified_times: false,
+            store_symlinks: false,
+            store_permissions: false,
+            checksum_algorithm: None,
         }
     }
-}
 
-#[async_trait]
-impl FileSource for S3Files {
-    type Error = S3Error;
+    /// Store modified times in `x-amz-meta-mtime` object metadata, so that
+    /// [`FileSource::set_modified`] works and listed files keep their original modified time
+    /// instead of the time they were uploaded. Defaults to `false`.
+    ///
+    /// Listing files then needs an extra `head_object` call for every object. Objects larger
+    /// than 5 GB can't have their metadata changed in place, so `set_modified` still returns
+    /// `false` for them.
+    pub fn store_modified_times(mut self, store_modified_times: bool) -> Self {
+        self.store_modified_times = store_modified_times;
+        self
+    }
 
-    async fn list_files(&mut self) -> Result<Vec<FileEntry>, Self::Error> {
-        let empty_path: PathBuf = PathBuf::new();
+    /// Store symbolic links as objects holding the link's target, marked with
+    /// `x-amz-meta-symlink-target` object metadata, so they are listed as links again and can
+    /// be synced back as links. Defaults to `false`, in which case links written to S3 are
+    /// plain objects holding the link's target.
+    ///
+    /// Listing files then needs an extra `head_object` call for every object.
+    pub fn store_symlinks(mut self, store_symlinks: bool) -> Self {
+        self.store_symlinks = store_symlinks;
+        self
+    }
+
+    /// Store file permissions in `x-amz-meta-mode`, `x-amz-meta-uid` and `x-amz-meta-gid`
+    /// object metadata, so that [`FileSource::set_permissions`] works and listed files have
+    /// [`FileEntry::permissions`]. Defaults to `false`.
+    ///
+    /// As with [`S3Files::store_modified_times`], listing files then needs an extra
+    /// `head_object` call for every object, and objects larger than 5 GB can't be updated.
+    pub fn store_permissions(mut self, store_permissions: bool) -> Self {
+        self.store_permissions = store_permissions;
+        self
+    }
+
+    /// Upload files with an S3 checksum using `algorithm`, such as `x-amz-checksum-sha256`,
+    /// and list them with that checksum as their [`FileEntry::digest`]. Defaults to `None`.
+    ///
+    /// S3 only keeps a checksum of the whole file for objects uploaded in a single request,
+    /// so files above the [multipart threshold](S3Files::multipart_threshold) are listed
+    /// without a digest. Listing files needs an extra `head_object` call for every object.
+    ///
+    /// S3 has no MD5 or BLAKE3 checksum, so [`HashAlgorithm::Md5`] and
+    /// [`HashAlgorithm::Blake3`] are the same as `None`. The ETag already holds the MD5 hash of
+    /// objects uploaded in a single request.
+    pub fn checksum_algorithm(mut self, algorithm: Option<HashAlgorithm>) -> Self {
+        self.checksum_algorithm =
+            algorithm.filter(|algorithm| checksum_algorithm(*algorithm).is_some());
+        self
+    }
+
+    /// Set the size above which files are uploaded using a multipart upload instead of a
+    /// single `put_object`. Defaults to [`DEFAULT_MULTIPART_THRESHOLD`].
+    ///
+    /// The ETags of files uploaded in parts are not plain MD5 hashes. To compare them against
+    /// local files, give [`LocalFiles::multipart_md5`](crate::local::LocalFiles::multipart_md5)
+    /// the same threshold and part size.
+    pub fn multipart_threshold(mut self, multipart_threshold: u64) -> Self {
+        self.multipart_threshold = multipart_threshold;
+        self
+    }
+
+    /// Set the size of each part of a multipart upload. Values below [`MIN_PART_SIZE`] are
+    /// treated as `MIN_PART_SIZE`. Defaults to [`DEFAULT_PART_SIZE`].
+    ///
+    /// S3 allows at most [`MAX_PARTS`] parts, so larger files fail with
+    /// [`S3Error::TooManyParts`]. The default part size allows files of up to about 78 GiB.
+    ///
+    /// At most `part_size * max_parallel_parts` bytes of a file are held in memory at once.
+    pub fn part_size(mut self, part_size: u64) -> Self {
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self
+    }
+
+    /// Set the maximum number of parts of a single file uploaded at once. Values below `1`
+    /// are treated as `1`. Defaults to `4`.
+    pub fn max_parallel_parts(mut self, max_parallel_parts: usize) -> Self {
+        self.max_parallel_parts = max_parallel_parts.max(1);
+        self
+    }
+
+    fn key(&self, path: &Path) -> String {
+        let mut key = self.prefix.clone();
+        key.push(path);
+        key.display().to_string()
+    }
+
+    /// Fill in each file's modified time, link target, permissions and checksum from its
+    /// metadata, if they are stored.
+    async fn read_stored_metadata(&self, files: &mut [FileEntry]) -> Result<(), S3Error> {
+        let heads = files
+            .iter()
+            .map(|file| {
+                self.client
+                    .head_object()
+                    .bucket(self.bucket.clone())
+                    .key(self.key(&file.path))
+                    .set_checksum_mode(self.checksum_algorithm.map(|_| ChecksumMode::Enabled))
+                    .send()
+            })
+            .collect::<Vec<_>>();
+        let mut heads = stream::iter(heads)
+            .buffered(HEAD_CONCURRENCY)
+            .zip(stream::iter(files));
+
+        while let Some((head, file)) = heads.next().await {
+            let head = head.map_err(S3Error::from_sdk)?;
+            self.apply_head(file, head);
+        }
+
+        Ok(())
+    }
+
+    /// Fill in a file's modified time, link target, permissions and checksum from the
+    /// response to a `head_object` call.
+    fn apply_head(&self, file: &mut FileEntry, head: HeadObjectOutput) {
+        if let Some(algorithm) = self.checksum_algorithm {
+            let checksum = match algorithm {
+                HashAlgorithm::Sha256 => head.checksum_sha256.as_deref(),
+                HashAlgorithm::Crc32c => head.checksum_crc32_c.as_deref(),
+                HashAlgorithm::Md5 | HashAlgorithm::Blake3 => None,
+            };
+            file.digest = checksum.and_then(|checksum| parse_checksum(algorithm, checksum));
+        }
+
+        let metadata = head.metadata.unwrap_or_default();
 
-        let response = self
+        let stored = metadata
+            .get(MTIME_METADATA)
+            .filter(|_| self.store_modified_times)
+            .and_then(|mtime| DateTime::parse_from_rfc3339(mtime).ok());
+        if let Some(stored) = stored {
+            file.modified = Some(stored.with_timezone(&Utc));
+        }
+
+        if self.store_symlinks {
+            file.link_target = metadata.get(LINK_TARGET_METADATA).map(PathBuf::from);
+        }
+
+        if self.store_permissions {
+            let id = |key| metadata.get(key).and_then(|id: &String| id.parse().ok());
+            let mode = metadata
+                .get(MODE_METADATA)
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok());
+            file.permissions = mode.map(|mode| FilePermissions {
+                mode,
+                uid: id(UID_METADATA),
+                gid: id(GID_METADATA),
+            });
+        }
+    }
+
+    /// Change some of an object's metadata by copying it over itself, keeping the rest of its
+    /// metadata, its content type and other headers, and its storage class. Values of `None`
+    /// are left as they are.
+    ///
+    /// Returns `false` if the object is too large to be copied in a single request.
+    async fn update_metadata<const N: usize>(
+        &self,
+        path: &Path,
+        values: [(&str, Option<String>); N],
+    ) -> Result<bool, S3Error> {
+        let key = self.key(path);
+        let head = self
             .client
-            .list_objects_v2()
+            .head_object()
+            .bucket(self.bucket.clone())
+            .key(key.clone())
+            .send()
+            .await
+            .map_err(S3Error::from_sdk)?;
+
+        if head.content_length > MAX_COPY_SIZE {
+            return Ok(false);
+        }
+
+        let mut metadata: HashMap<String, String> = head.metadata.unwrap_or_default();
+        for (name, value) in values {
+            if let Some(value) = value {
+                metadata.insert(name.to_owned(), value);
+            }
+        }
+
+        self.client
+            .copy_object()
             .bucket(self.bucket.clone())
-            .prefix(self.prefix.display().to_string())
+            .key(key.clone())
+            .copy_source(format!("{}/{}", self.bucket, encode_copy_source(&key)))
+            .metadata_directive(MetadataDirective::Replace)
+            .set_metadata(Some(metadata))
+            .set_content_type(head.content_type)
+            .set_cache_control(head.cache_control)
+            .set_content_disposition(head.content_disposition)
+            .set_content_encoding(head.content_encoding)
+            .set_content_language(head.content_language)
+            .set_expires(head.expires)
+            .set_website_redirect_location(head.website_redirect_location)
+            .set_storage_class(head.storage_class)
+            .set_checksum_algorithm(self.checksum_algorithm.and_then(checksum_algorithm))
             .send()
             .await
-            .map_err(aws_sdk_s3::Error::from)?;
+            .map_err(S3Error::from_sdk)?;
+
+        Ok(true)
+    }
+
+    async fn put_object(
+        &self,
+        key: String,
+        bytes: Bytes,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Result<(), S3Error> {
+        let checksum =
+            |algorithm: HashAlgorithm| Some(BASE64.encode(algorithm.digest(&bytes).value));
+        let request = match self.checksum_algorithm {
+            Some(HashAlgorithm::Sha256) => self
+                .client
+                .put_object()
+                .set_checksum_sha256(checksum(HashAlgorithm::Sha256)),
+            Some(HashAlgorithm::Crc32c) => self
+                .client
+                .put_object()
+                .set_checksum_crc32_c(checksum(HashAlgorithm::Crc32c)),
+            Some(HashAlgorithm::Md5 | HashAlgorithm::Blake3) | None => self.client.put_object(),
+        };
+
+        request
+            .bucket(self.bucket.clone())
+            .key(key)
+            .body(ByteStream::from(bytes))
+            .set_metadata(metadata)
+            .send()
+            .await
+            .map_err(S3Error::from_sdk)?;
+
+        Ok(())
+    }
+
+    /// Upload the rest of `stream` (after what is already in `buffer`) in parts. If any part
+    /// fails, the upload is aborted so S3 doesn't keep the parts that were uploaded.
+    async fn multipart_upload(
+        &self,
+        key: String,
+        buffer: BytesMut,
+        stream: FileStream,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Result<(), S3Error> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(self.bucket.clone())
+            .key(key.clone())
+            .set_metadata(metadata)
+            .send()
+            .await
+            .map_err(S3Error::from_sdk)?;
+        let upload_id = upload.upload_id.ok_or(S3Error::MissingUploadId)?;
+
+        let result = async {
+            let parts = self.upload_parts(&key, &upload_id, buffer, stream).await?;
+
+            self.client
+                .complete_multipart_upload()
+                .bucket(self.bucket.clone())
+                .key(key.clone())
+                .upload_id(upload_id.clone())
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(S3Error::from_sdk)?;
+
+            Ok(())
+        }
+        .await;
+
+        if result.is_err() {
+            // The original error is more useful than any error from aborting.
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(self.bucket.clone())
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+        }
+
+        result
+    }
+
+    async fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        mut buffer: BytesMut,
+        mut stream: FileStream,
+    ) -> Result<Vec<CompletedPart>, S3Error> {
+        let part_size = self.part_size as usize;
+        let mut uploads = FuturesUnordered::new();
+        let mut parts = vec![];
+        let mut part_number = 0;
+        let mut finished = false;
+
+        loop {
+            // Start a part once a whole one is buffered, or the stream has ended.
+            while uploads.len() < self.max_parallel_parts
+                && (buffer.len() >= part_size || (finished && !buffer.is_empty()))
+            {
+                if part_number == MAX_PARTS {
+                    return Err(S3Error::TooManyParts {
+                        part_size: self.part_size,
+                    });
+                }
+
+                let body = buffer.split_to(buffer.len().min(part_size)).freeze();
+                part_number += 1;
+                uploads.push(self.upload_part(key, upload_id, part_number, body));
+            }
+
+            // Only read as far ahead in the stream as there are free upload slots, and keep
+            // polling the parts in flight while reading so they don't stall.
+            let reading = !finished && uploads.len() < self.max_parallel_parts;
+            if reading && !uploads.is_empty() {
+                match future::select(stream.try_next(), uploads.try_next()).await {
+                    Either::Left((chunk, _)) => match chunk? {
+                        Some(chunk) => buffer.extend_from_slice(&chunk),
+                        None => finished = true,
+                    },
+                    Either::Right((part, _)) => parts.extend(part?),
+                }
+            } else if reading {
+                match stream.try_next().await? {
+                    Some(chunk) => buffer.extend_from_slice(&chunk),
+                    None => finished = true,
+                }
+            } else {
+                match uploads.try_next().await? {
+                    Some(part) => parts.push(part),
+                    None => break,
+                }
+            }
+        }
+
+        parts.sort_by_key(|part| part.part_number());
+        Ok(parts)
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: Bytes,
+    ) -> Result<CompletedPart, S3Error> {
+        let output = self
+            .client
+            .upload_part()
+            .bucket(self.bucket.clone())
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(S3Error::from_sdk)?;
+
+        Ok(CompletedPart::builder()
+            .set_e_tag(output.e_tag)
+            .part_number(part_number)
+            .build())
+    }
+}
+
+/// Format a modified time for the object metadata, to the nanosecond.
+fn format_mtime(modified: DateTime<Utc>) -> String {
+    modified.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+/// The object metadata for a file's permissions. Unknown owners and groups are `None`.
+fn permissions_metadata(permissions: FilePermissions) -> [(&'static str, Option<String>); 3] {
+    [
+        (MODE_METADATA, Some(format!("{:o}", permissions.mode))),
+        (UID_METADATA, permissions.uid.map(|uid| uid.to_string())),
+        (GID_METADATA, permissions.gid.map(|gid| gid.to_string())),
+    ]
+}
+
+/// The S3 name for a hash algorithm, if S3 has a checksum for it.
+fn checksum_algorithm(algorithm: HashAlgorithm) -> Option<ChecksumAlgorithm> {
+    match algorithm {
+        HashAlgorithm::Sha256 => Some(ChecksumAlgorithm::Sha256),
+        HashAlgorithm::Crc32c => Some(ChecksumAlgorithm::Crc32C),
+        HashAlgorithm::Md5 | HashAlgorithm::Blake3 => None,
+    }
+}
+
+/// Parse a base64 checksum returned by S3. Checksums of multipart uploads, which are
+/// checksums of each part's checksum followed by `-` and the number of parts, are ignored.
+fn parse_checksum(algorithm: HashAlgorithm, checksum: &str) -> Option<Digest> {
+    if checksum.contains('-') {
+        return None;
+    }
+
+    let value = BASE64.decode(checksum).ok()?;
+    Some(Digest { algorithm, value })
+}
+
+/// Percent-encode an object key for use in the `x-amz-copy-source` header.
+fn encode_copy_source(key: &str) -> String {
+    let mut encoded = String::new();
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Parse an ETag as an MD5 hash. ETags of objects uploaded in parts look like `<hash>-<parts>`.
+fn parse_etag(etag: &str) -> (Option<u128>, Option<u32>) {
+    let etag = etag.trim_matches('"');
+    let (hash, parts) = match etag.split_once('-') {
+        Some((hash, parts)) => match parts.parse() {
+            Ok(parts) => (hash, Some(parts)),
+            Err(_) => return (None, None),
+        },
+        None => (etag, None),
+    };
+
+    match u128::from_str_radix(hash, 16) {
+        Ok(digest) => (Some(digest), parts),
+        Err(_) => (None, None),
+    }
+}
+
+#[async_trait]
+impl FileSource for S3Files {
+    type Error = S3Error;
+
+    async fn list_files(&self) -> Result<Vec<FileEntry>, Self::Error> {
+        let empty_path: PathBuf = PathBuf::new();
 
         let mut files = vec![];
+        let mut continuation_token = None;
+
+        // Each response holds at most 1000 objects, so follow the continuation token until
+        // every page has been read.
+        loop {
+            let response = self
+                .client
+                .list_objects_v2()
+                .bucket(self.bucket.clone())
+                .prefix(self.prefix.display().to_string())
+                .set_continuation_token(continuation_token.take())
+                .send()
+                .await
+                .map_err(S3Error::from_sdk)?;
 
-        if let Some(contents) = response.contents {
-            for object in contents {
+            for object in response.contents.unwrap_or_default() {
                 let key: PathBuf = object
                     .key
                     .as_ref()
@@ -86,21 +671,12 @@ impl FileSource for S3Files {
 
                 if key != empty_path {
                     let modified = object.last_modified.and_then(|date_time| {
-                        NaiveDateTime::from_timestamp_opt(
-                            date_time.secs(),
-                            date_time.subsec_nanos(),
-                        )
-                        .map(|x| x.and_utc())
+                        DateTime::from_timestamp(date_time.secs(), date_time.subsec_nanos())
                     });
 
-                    let md5_hash = match self.use_etag_as_hash {
-                        true => object.e_tag.and_then(|etag| {
-                            let digest: Option<u128> =
-                                u128::from_str_radix(etag.trim_matches('"'), 16).ok();
-
-                            digest
-                        }),
-                        false => None,
+                    let (md5_hash, md5_parts) = match self.use_etag_as_hash {
+                        true => object.e_tag.as_deref().map_or((None, None), parse_etag),
+                        false => (None, None),
                     };
 
                     files.push(FileEntry {
@@ -108,18 +684,76 @@ impl FileSource for S3Files {
                         size: u64::try_from(object.size).ok(),
                         modified,
                         md5_hash,
+                        md5_parts,
+                        link_target: None,
+                        digest: None,
+                        permissions: None,
                     });
                 }
             }
+
+            match response.next_continuation_token {
+                Some(token) if response.is_truncated => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+
+        if self.store_modified_times
+            || self.store_symlinks
+            || self.store_permissions
+            || self.checksum_algorithm.is_some()
+        {
+            self.read_stored_metadata(&mut files).await?;
         }
 
         Ok(files)
     }
 
-    async fn read_file<P: AsRef<Path> + Send>(&mut self, path: P) -> Result<Vec<u8>, Self::Error> {
-        let mut key = self.prefix.clone();
-        key.push(path.as_ref());
-        let key = key.display().to_string();
+    /// Looks the object up with a single `head_object` call.
+    async fn stat_file<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+    ) -> Result<Option<FileEntry>, Self::Error> {
+        let path = path.as_ref();
+
+        let head = self
+            .client
+            .head_object()
+            .bucket(self.bucket.clone())
+            .key(self.key(path))
+            .set_checksum_mode(self.checksum_algorithm.map(|_| ChecksumMode::Enabled))
+            .send()
+            .await;
+        let head = match head {
+            Ok(head) => head,
+            Err(SdkError::ServiceError(error)) if error.err().is_not_found() => return Ok(None),
+            Err(error) => return Err(S3Error::from_sdk(error)),
+        };
+
+        let (md5_hash, md5_parts) = match self.use_etag_as_hash {
+            true => head.e_tag.as_deref().map_or((None, None), parse_etag),
+            false => (None, None),
+        };
+
+        let mut file = FileEntry {
+            path: path.to_owned(),
+            size: u64::try_from(head.content_length).ok(),
+            modified: head.last_modified.and_then(|date_time| {
+                DateTime::from_timestamp(date_time.secs(), date_time.subsec_nanos())
+            }),
+            md5_hash,
+            md5_parts,
+            link_target: None,
+            digest: None,
+            permissions: None,
+        };
+        self.apply_head(&mut file, head);
+
+        Ok(Some(file))
+    }
+
+    async fn read_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>, Self::Error> {
+        let key = self.key(path.as_ref());
 
         let output = self
             .client
@@ -128,41 +762,202 @@ impl FileSource for S3Files {
             .key(key)
             .send()
             .await
-            .map_err(aws_sdk_s3::Error::from)?;
+            .map_err(S3Error::from_sdk)?;
 
         let stream = output.body.collect().await?.to_vec();
 
         Ok(stream)
     }
 
-    async fn write_file<P: AsRef<Path> + Send>(
-        &mut self,
+    async fn read_stream<P: AsRef<Path> + Send>(&self, path: P) -> Result<FileStream, Self::Error> {
+        let key = self.key(path.as_ref());
+
+        let output = self
+            .client
+            .get_object()
+            .bucket(self.bucket.clone())
+            .key(key)
+            .send()
+            .await
+            .map_err(S3Error::from_sdk)?;
+
+        Ok(Box::pin(output.body.map_err(std::io::Error::other)))
+    }
+
+    /// Files up to the multipart threshold are written with a single `put_object` call.
+    /// Larger files are uploaded in parts, without reading the whole file into memory.
+    async fn write_stream<P: AsRef<Path> + Send>(
+        &self,
         path: P,
-        bytes: &[u8],
+        stream: FileStream,
     ) -> Result<(), Self::Error> {
-        let mut key = self.prefix.clone();
-        key.push(path.as_ref());
-        let key = key.display().to_string();
+        self.write_stream_with(path, stream, FileAttributes::default())
+            .await?;
+        Ok(())
+    }
+
+    /// The modified time and permissions are stored in the object's metadata as it is
+    /// uploaded, if [`S3Files::store_modified_times`] and [`S3Files::store_permissions`] are
+    /// set.
+    async fn write_stream_with<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        mut stream: FileStream,
+        attributes: FileAttributes,
+    ) -> Result<FileAttributes, Self::Error> {
+        let key = self.key(path.as_ref());
+
+        let stored = FileAttributes {
+            modified: attributes.modified.filter(|_| self.store_modified_times),
+            permissions: attributes.permissions.filter(|_| self.store_permissions),
+        };
+        let mut metadata = HashMap::new();
+        if let Some(modified) = stored.modified {
+            metadata.insert(MTIME_METADATA.to_owned(), format_mtime(modified));
+        }
+        if let Some(permissions) = stored.permissions {
+            for (key, value) in permissions_metadata(permissions) {
+                if let Some(value) = value {
+                    metadata.insert(key.to_owned(), value);
+                }
+            }
+        }
+        let metadata = Some(metadata).filter(|metadata| !metadata.is_empty());
+
+        let mut buffer = BytesMut::new();
+        while buffer.len() as u64 <= self.multipart_threshold {
+            match stream.try_next().await? {
+                Some(chunk) => buffer.extend_from_slice(&chunk),
+                None => {
+                    self.put_object(key, buffer.freeze(), metadata).await?;
+                    return Ok(stored);
+                }
+            }
+        }
+
+        self.multipart_upload(key, buffer, stream, metadata).await?;
+        Ok(stored)
+    }
+
+    /// Only supported if [`S3Files::store_symlinks`] is set.
+    async fn write_link<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        target: &Path,
+    ) -> Result<bool, Self::Error> {
+        if !self.store_symlinks {
+            return Ok(false);
+        }
 
-        let stream = aws_sdk_s3::primitives::ByteStream::from(bytes.to_owned());
+        let target = target.display().to_string();
+        let metadata = HashMap::from([(LINK_TARGET_METADATA.to_owned(), target.clone())]);
+        self.put_object(self.key(path.as_ref()), Bytes::from(target), Some(metadata))
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Copies the object server-side with `copy_object`, keeping its metadata. Objects larger
+    /// than 5 GB can't be copied in a single request, so aren't supported.
+    async fn copy_file<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<bool, Self::Error> {
+        let from = self.key(from.as_ref());
+        let head = self
+            .client
+            .head_object()
+            .bucket(self.bucket.clone())
+            .key(from.clone())
+            .send()
+            .await
+            .map_err(S3Error::from_sdk)?;
+
+        if head.content_length > MAX_COPY_SIZE {
+            return Ok(false);
+        }
 
         self.client
-            .put_object()
+            .copy_object()
+            .bucket(self.bucket.clone())
+            .key(self.key(to.as_ref()))
+            .copy_source(format!("{}/{}", self.bucket, encode_copy_source(&from)))
+            .set_checksum_algorithm(self.checksum_algorithm.and_then(checksum_algorithm))
+            .send()
+            .await
+            .map_err(S3Error::from_sdk)?;
+
+        Ok(true)
+    }
+
+    /// S3 can't move objects, so the object is copied server-side, then the original is
+    /// deleted. (See [`S3Files::copy_file`](FileSource::copy_file).)
+    async fn rename_file<P: AsRef<Path> + Send>(
+        &self,
+        from: P,
+        to: P,
+    ) -> Result<bool, Self::Error> {
+        let from = from.as_ref();
+        if !self.copy_file(from, to.as_ref()).await? {
+            return Ok(false);
+        }
+        self.delete_file(from).await?;
+
+        Ok(true)
+    }
+
+    async fn delete_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<(), Self::Error> {
+        let key = self.key(path.as_ref());
+
+        self.client
+            .delete_object()
             .bucket(self.bucket.clone())
             .key(key)
-            .body(stream)
             .send()
             .await
-            .map_err(aws_sdk_s3::Error::from)?;
+            .map_err(S3Error::from_sdk)?;
 
         Ok(())
     }
 
+    /// Only supported if [`S3Files::store_modified_times`] is set. The object is copied over
+    /// itself with the new time in its metadata, keeping its other metadata and headers such
+    /// as its content type.
+    ///
+    /// Syncs store modified times as files are uploaded, so this is only needed for objects
+    /// that are already up to date. The copy gives objects uploaded in parts a plain MD5
+    /// ETag, as S3 copies them in a single request.
     async fn set_modified<P: AsRef<Path> + Send>(
-        &mut self,
-        _path: P,
-        _modified: Option<DateTime<Utc>>,
+        &self,
+        path: P,
+        modified: Option<DateTime<Utc>>,
+    ) -> Result<bool, Self::Error> {
+        let Some(modified) = modified.filter(|_| self.store_modified_times) else {
+            return Ok(false);
+        };
+
+        let mtime = format_mtime(modified);
+        self.update_metadata(path.as_ref(), [(MTIME_METADATA, Some(mtime))])
+            .await
+    }
+
+    /// Only supported if [`S3Files::store_permissions`] is set. The object is copied over
+    /// itself in the same way as for [`FileSource::set_modified`].
+    async fn set_permissions<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        permissions: FilePermissions,
     ) -> Result<bool, Self::Error> {
-        Ok(false)
+        if !self.store_permissions {
+            return Ok(false);
+        }
+
+        self.update_metadata(path.as_ref(), permissions_metadata(permissions))
+            .await
+    }
+
+    fn digest_algorithm(&self) -> Option<HashAlgorithm> {
+        self.checksum_algorithm
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        error.is_transient()
     }
 }
@@ -1,48 +1,191 @@
 #![cfg(test)]
 
 use std::{
-    path::Path,
-    sync::{Arc, atomic::AtomicU64},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
 };
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use chrono::{DateTime, TimeZone, Utc};
+use futures::{TryStreamExt, stream};
 use pretty_assertions::assert_eq;
 use thiserror::Error as ErrorTrait;
 
-use crate::{FileEntry, FileSource};
+use crate::{
+    FileEntry, FileSource, FileStream, HashAlgorithm, SyncReport, SyncState, TwoWayReport,
+};
 
 #[derive(Debug, ErrorTrait)]
-#[error("Some error occurred.")]
-pub struct TestError;
+pub enum TestError {
+    #[error("Some error occurred.")]
+    Failed,
+
+    #[error("A transient error occurred.")]
+    Transient,
+}
+
+impl From<std::io::Error> for TestError {
+    fn from(_: std::io::Error) -> Self {
+        TestError::Failed
+    }
+}
+
+type WriteHook = dyn Fn(&Path, &mut Vec<u8>) + Send + Sync;
 
 pub struct TestSource {
-    files: Vec<(FileEntry, Vec<u8>)>,
+    files: Mutex<Vec<(FileEntry, Vec<u8>)>>,
     clock: Option<Arc<AtomicU64>>,
     use_hashes: bool,
+    digest_algorithm: Option<HashAlgorithm>,
+    unreadable: Vec<PathBuf>,
+    unwritable: Vec<PathBuf>,
+    transient_failures: AtomicU64,
+    on_write: Option<Box<WriteHook>>,
+    write_delay: Option<Duration>,
+    writes_in_flight: AtomicUsize,
+    max_writes_in_flight: AtomicUsize,
+    drop_modified_times: bool,
 }
 
 impl TestSource {
     pub fn new(clock: Option<Arc<AtomicU64>>, use_hashes: bool) -> Self {
         TestSource {
-            files: vec![],
+            files: Mutex::new(vec![]),
             clock,
             use_hashes,
+            digest_algorithm: None,
+            unreadable: vec![],
+            unwritable: vec![],
+            transient_failures: AtomicU64::new(0),
+            on_write: None,
+            write_delay: None,
+            writes_in_flight: AtomicUsize::new(0),
+            max_writes_in_flight: AtomicUsize::new(0),
+            drop_modified_times: false,
         }
     }
+
+    /// List files with a digest using `algorithm`.
+    pub fn digest_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.digest_algorithm = Some(algorithm);
+        self
+    }
+
+    /// Fail every read of `path`.
+    pub fn unreadable<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.unreadable.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Fail every stream write to `path`, after the write delay.
+    pub fn unwritable<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.unwritable.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Fail the next `failures` reads with a transient error.
+    pub fn transient_failures(self, failures: u64) -> Self {
+        self.transient_failures.store(failures, Ordering::SeqCst);
+        self
+    }
+
+    /// Change the bytes of each file written from a stream before storing them.
+    pub fn on_write<F>(mut self, on_write: F) -> Self
+    where
+        F: Fn(&Path, &mut Vec<u8>) + Send + Sync + 'static,
+    {
+        self.on_write = Some(Box::new(on_write));
+        self
+    }
+
+    /// Take `delay` to write each file from a stream. Needs a Tokio runtime.
+    pub fn write_delay(mut self, delay: Duration) -> Self {
+        self.write_delay = Some(delay);
+        self
+    }
+
+    /// Ignore calls to `set_modified`, like a source that can't store modified times.
+    pub fn drop_modified_times(mut self) -> Self {
+        self.drop_modified_times = true;
+        self
+    }
+
+    pub fn files(&self) -> Vec<(FileEntry, Vec<u8>)> {
+        self.files.lock().unwrap().clone()
+    }
+
+    /// The most stream writes that were ever in progress at once.
+    pub fn max_writes_in_flight(&self) -> usize {
+        self.max_writes_in_flight.load(Ordering::SeqCst)
+    }
 }
 
 #[async_trait]
 impl FileSource for TestSource {
     type Error = TestError;
 
-    async fn list_files(&mut self) -> Result<Vec<FileEntry>, Self::Error> {
-        Ok(self.files.iter().map(|x| x.0.clone()).collect())
+    async fn list_files(&self) -> Result<Vec<FileEntry>, Self::Error> {
+        Ok(self.files().into_iter().map(|x| x.0).collect())
     }
 
-    async fn read_file<P: AsRef<Path> + Send>(&mut self, path: P) -> Result<Vec<u8>, Self::Error> {
+    async fn read_stream<P: AsRef<Path> + Send>(&self, path: P) -> Result<FileStream, Self::Error> {
+        if self
+            .unreadable
+            .iter()
+            .any(|unreadable| unreadable == path.as_ref())
+        {
+            return Err(TestError::Failed);
+        }
+        let failing =
+            self.transient_failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                    left.checked_sub(1)
+                });
+        if failing.is_ok() {
+            return Err(TestError::Transient);
+        }
+
+        let bytes = Bytes::from(self.read_file(path).await?);
+        Ok(Box::pin(stream::once(async { Ok(bytes) })))
+    }
+
+    async fn write_stream<P: AsRef<Path> + Send>(
+        &self,
+        path: P,
+        stream: FileStream,
+    ) -> Result<(), Self::Error> {
+        if let Some(delay) = self.write_delay {
+            let in_flight = self.writes_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_writes_in_flight
+                .fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(delay).await;
+            self.writes_in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+        if self
+            .unwritable
+            .iter()
+            .any(|unwritable| unwritable == path.as_ref())
+        {
+            return Err(TestError::Failed);
+        }
+
+        let mut bytes = stream.try_collect::<Vec<Bytes>>().await?.concat();
+        if let Some(on_write) = &self.on_write {
+            on_write(path.as_ref(), &mut bytes);
+        }
+        self.write_file(path, &bytes).await
+    }
+
+    async fn read_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<Vec<u8>, Self::Error> {
         Ok(self
             .files
+            .lock()
+            .unwrap()
             .iter()
             .find(|x| x.0.path == path.as_ref())
             .unwrap()
@@ -51,7 +194,7 @@ impl FileSource for TestSource {
     }
 
     async fn write_file<P: AsRef<Path> + Send>(
-        &mut self,
+        &self,
         path: P,
         bytes: &[u8],
     ) -> Result<(), Self::Error> {
@@ -68,13 +211,18 @@ impl FileSource for TestSource {
             u128::from_be_bytes(bytes)
         });
 
-        self.files.retain(|entry| entry.0.path != path);
-        self.files.push((
+        let mut files = self.files.lock().unwrap();
+        files.retain(|entry| entry.0.path != path);
+        files.push((
             FileEntry {
                 path: path.to_owned(),
                 size: Some(bytes.len() as u64),
                 modified,
                 md5_hash,
+                digest: self
+                    .digest_algorithm
+                    .map(|algorithm| algorithm.digest(bytes)),
+                ..Default::default()
             },
             bytes.to_owned(),
         ));
@@ -82,12 +230,39 @@ impl FileSource for TestSource {
         Ok(())
     }
 
+    async fn copy_file<P: AsRef<Path> + Send>(&self, from: P, to: P) -> Result<bool, Self::Error> {
+        let bytes = self.read_file(from).await?;
+        self.write_file(to, &bytes).await?;
+        Ok(true)
+    }
+
+    async fn rename_file<P: AsRef<Path> + Send>(
+        &self,
+        from: P,
+        to: P,
+    ) -> Result<bool, Self::Error> {
+        self.copy_file(from.as_ref(), to.as_ref()).await?;
+        self.delete_file(from).await?;
+        Ok(true)
+    }
+
+    async fn delete_file<P: AsRef<Path> + Send>(&self, path: P) -> Result<(), Self::Error> {
+        let mut files = self.files.lock().unwrap();
+        files.retain(|entry| entry.0.path != path.as_ref());
+        Ok(())
+    }
+
     async fn set_modified<P: AsRef<Path> + Send>(
-        &mut self,
+        &self,
         path: P,
         modified: Option<DateTime<Utc>>,
     ) -> Result<bool, Self::Error> {
-        let entry = self.files.iter_mut().find(|x| x.0.path == path.as_ref());
+        if self.drop_modified_times {
+            return Ok(false);
+        }
+
+        let mut files = self.files.lock().unwrap();
+        let entry = files.iter_mut().find(|x| x.0.path == path.as_ref());
         if let (Some(entry), Some(modified)) = (entry, modified) {
             entry.0.modified = Some(modified);
             Ok(true)
@@ -95,19 +270,27 @@ impl FileSource for TestSource {
             Ok(false)
         }
     }
+
+    fn digest_algorithm(&self) -> Option<HashAlgorithm> {
+        self.digest_algorithm
+    }
+
+    fn is_transient(error: &Self::Error) -> bool {
+        matches!(error, TestError::Transient)
+    }
 }
 
 #[test]
 fn sync_nothing_to_nothing() {
     let clock = Arc::new(AtomicU64::new(0));
 
-    let mut from = TestSource::new(Some(Arc::clone(&clock)), false);
-    let mut to = TestSource::new(Some(Arc::clone(&clock)), false);
+    let from = TestSource::new(Some(Arc::clone(&clock)), false);
+    let to = TestSource::new(Some(Arc::clone(&clock)), false);
 
-    pollster::block_on(crate::sync_one_way(&mut from, &mut to)).unwrap();
+    pollster::block_on(crate::sync_one_way(&from, &to)).unwrap();
 
-    assert_eq!(&from.files, &[]);
-    assert_eq!(&to.files, &[]);
+    assert_eq!(&from.files(), &[]);
+    assert_eq!(&to.files(), &[]);
 }
 
 #[test]
@@ -119,13 +302,13 @@ fn sync_process_can_be_spawned() {
     async fn test_fn() {
         let clock = Arc::new(AtomicU64::new(0));
 
-        let mut from = TestSource::new(Some(Arc::clone(&clock)), false);
-        let mut to = TestSource::new(Some(Arc::clone(&clock)), false);
+        let from = TestSource::new(Some(Arc::clone(&clock)), false);
+        let to = TestSource::new(Some(Arc::clone(&clock)), false);
 
-        crate::sync_one_way(&mut from, &mut to).await.unwrap();
+        crate::sync_one_way(&from, &to).await.unwrap();
 
-        assert_eq!(&from.files, &[]);
-        assert_eq!(&to.files, &[]);
+        assert_eq!(&from.files(), &[]);
+        assert_eq!(&to.files(), &[]);
     }
 
     use tokio::runtime::Runtime;
@@ -138,33 +321,33 @@ fn sync_process_can_be_spawned() {
 fn sync_file_to_nothing() {
     let clock = Arc::new(AtomicU64::new(0));
 
-    let mut from = TestSource::new(Some(Arc::clone(&clock)), false);
+    let from = TestSource::new(Some(Arc::clone(&clock)), false);
     pollster::block_on(from.write_file("one.txt", b"one")).unwrap();
 
-    let mut to = TestSource::new(Some(Arc::clone(&clock)), false);
+    let to = TestSource::new(Some(Arc::clone(&clock)), false);
 
-    pollster::block_on(crate::sync_one_way(&mut from, &mut to)).unwrap();
+    pollster::block_on(crate::sync_one_way(&from, &to)).unwrap();
 
     assert_eq!(
-        &from.files,
+        &from.files(),
         &[(
             FileEntry {
                 path: "one.txt".into(),
                 size: Some(3),
                 modified: Some(chrono::Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap()),
-                md5_hash: None,
+                ..Default::default()
             },
             b"one".to_vec()
         )]
     );
     assert_eq!(
-        &to.files,
+        &to.files(),
         &[(
             FileEntry {
                 path: "one.txt".into(),
                 size: Some(3),
                 modified: Some(chrono::Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap()),
-                md5_hash: None,
+                ..Default::default()
             },
             b"one".to_vec()
         )]
@@ -175,8 +358,8 @@ fn sync_file_to_nothing() {
 fn only_sync_more_recent_files() {
     let clock = Arc::new(AtomicU64::new(0));
 
-    let mut from = TestSource::new(Some(Arc::clone(&clock)), false);
-    let mut to = TestSource::new(Some(Arc::clone(&clock)), false);
+    let from = TestSource::new(Some(Arc::clone(&clock)), false);
+    let to = TestSource::new(Some(Arc::clone(&clock)), false);
 
     pollster::block_on(from.write_file("first_in_from.txt", b"old")).unwrap();
     pollster::block_on(to.write_file("first_in_to.txt", b"old")).unwrap();
@@ -184,17 +367,17 @@ fn only_sync_more_recent_files() {
     pollster::block_on(to.write_file("first_in_from.txt", b"changed")).unwrap();
     pollster::block_on(from.write_file("first_in_to.txt", b"changed")).unwrap();
 
-    pollster::block_on(crate::sync_one_way(&mut from, &mut to)).unwrap();
+    pollster::block_on(crate::sync_one_way(&from, &to)).unwrap();
 
     assert_eq!(
-        &from.files,
+        &from.files(),
         &[
             (
                 FileEntry {
                     path: "first_in_from.txt".into(),
                     size: Some(3),
                     modified: Some(chrono::Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap()),
-                    md5_hash: None,
+                    ..Default::default()
                 },
                 b"old".to_vec()
             ),
@@ -203,7 +386,7 @@ fn only_sync_more_recent_files() {
                     path: "first_in_to.txt".into(),
                     size: Some(7),
                     modified: Some(chrono::Utc.with_ymd_and_hms(2000, 1, 4, 0, 0, 0).unwrap()),
-                    md5_hash: None,
+                    ..Default::default()
                 },
                 b"changed".to_vec()
             ),
@@ -211,14 +394,14 @@ fn only_sync_more_recent_files() {
     );
 
     assert_eq!(
-        &to.files,
+        &to.files(),
         &[
             (
                 FileEntry {
                     path: "first_in_from.txt".into(),
                     size: Some(7),
                     modified: Some(chrono::Utc.with_ymd_and_hms(2000, 1, 3, 0, 0, 0).unwrap()),
-                    md5_hash: None,
+                    ..Default::default()
                 },
                 b"changed".to_vec()
             ),
@@ -227,7 +410,7 @@ fn only_sync_more_recent_files() {
                     path: "first_in_to.txt".into(),
                     size: Some(7),
                     modified: Some(chrono::Utc.with_ymd_and_hms(2000, 1, 4, 0, 0, 0).unwrap()),
-                    md5_hash: None,
+                    ..Default::default()
                 },
                 b"changed".to_vec()
             ),
@@ -237,8 +420,8 @@ fn only_sync_more_recent_files() {
 
 #[test]
 fn sync_based_on_size_if_lacking_timestamps() {
-    let mut from = TestSource::new(None, false);
-    let mut to = TestSource::new(None, false);
+    let from = TestSource::new(None, false);
+    let to = TestSource::new(None, false);
 
     pollster::block_on(from.write_file("one.txt", b"on")).unwrap();
     pollster::block_on(from.write_file("two.txt", b"too")).unwrap();
@@ -248,17 +431,16 @@ fn sync_based_on_size_if_lacking_timestamps() {
     pollster::block_on(to.write_file("two.txt", b"two")).unwrap();
     pollster::block_on(to.write_file("three.txt", b"three")).unwrap();
 
-    pollster::block_on(crate::sync_one_way(&mut from, &mut to)).unwrap();
+    pollster::block_on(crate::sync_one_way(&from, &to)).unwrap();
 
     assert_eq!(
-        &to.files,
+        &to.files(),
         &[
             (
                 FileEntry {
                     path: "two.txt".into(),
                     size: Some(3),
-                    modified: None,
-                    md5_hash: None,
+                    ..Default::default()
                 },
                 b"two".to_vec()
             ),
@@ -266,8 +448,7 @@ fn sync_based_on_size_if_lacking_timestamps() {
                 FileEntry {
                     path: "one.txt".into(),
                     size: Some(2),
-                    modified: None,
-                    md5_hash: None,
+                    ..Default::default()
                 },
                 b"on".to_vec()
             ),
@@ -275,8 +456,7 @@ fn sync_based_on_size_if_lacking_timestamps() {
                 FileEntry {
                     path: "three.txt".into(),
                     size: Some(7),
-                    modified: None,
-                    md5_hash: None,
+                    ..Default::default()
                 },
                 b"threeee".to_vec()
             ),
@@ -286,8 +466,8 @@ fn sync_based_on_size_if_lacking_timestamps() {
 
 #[test]
 fn sync_based_on_hash_if_size_fails() {
-    let mut from = TestSource::new(None, true);
-    let mut to = TestSource::new(None, true);
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true);
 
     pollster::block_on(from.write_file("one.txt", b"won")).unwrap();
     pollster::block_on(from.write_file("two.txt", b"two")).unwrap();
@@ -295,18 +475,18 @@ fn sync_based_on_hash_if_size_fails() {
     pollster::block_on(to.write_file("one.txt", b"one")).unwrap();
     pollster::block_on(to.write_file("two.txt", b"two")).unwrap();
 
-    pollster::block_on(crate::sync_one_way(&mut from, &mut to)).unwrap();
+    pollster::block_on(crate::sync_one_way(&from, &to)).unwrap();
 
     // NOTE: The order proves that `two` was not written.
     assert_eq!(
-        &to.files,
+        &to.files(),
         &[
             (
                 FileEntry {
                     path: "two.txt".into(),
                     size: Some(3),
-                    modified: None,
                     md5_hash: Some(245460460880478039906047464050106960481),
+                    ..Default::default()
                 },
                 b"two".to_vec()
             ),
@@ -314,8 +494,8 @@ fn sync_based_on_hash_if_size_fails() {
                 FileEntry {
                     path: "one.txt".into(),
                     size: Some(3),
-                    modified: None,
                     md5_hash: Some(164013335976871257125904378601358726325),
+                    ..Default::default()
                 },
                 b"won".to_vec()
             ),
@@ -325,26 +505,26 @@ fn sync_based_on_hash_if_size_fails() {
 
 #[test]
 fn size_and_hash_matching_bypasses_modified_date() {
-    let mut from = TestSource::new(None, true);
-    let mut to = TestSource::new(None, true);
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true);
 
     pollster::block_on(to.write_file("one.txt", b"one")).unwrap();
     pollster::block_on(to.write_file("two.txt", b"two")).unwrap();
 
     pollster::block_on(from.write_file("one.txt", b"one")).unwrap();
 
-    pollster::block_on(crate::sync_one_way(&mut from, &mut to)).unwrap();
+    pollster::block_on(crate::sync_one_way(&from, &to)).unwrap();
 
     // NOTE: The order proves that `one` was not written.
     assert_eq!(
-        &to.files,
+        &to.files(),
         &[
             (
                 FileEntry {
                     path: "one.txt".into(),
                     size: Some(3),
-                    modified: None,
                     md5_hash: Some(331623505319187781935359225974189632386),
+                    ..Default::default()
                 },
                 b"one".to_vec()
             ),
@@ -352,11 +532,951 @@ fn size_and_hash_matching_bypasses_modified_date() {
                 FileEntry {
                     path: "two.txt".into(),
                     size: Some(3),
-                    modified: None,
                     md5_hash: Some(245460460880478039906047464050106960481),
+                    ..Default::default()
                 },
                 b"two".to_vec()
             ),
         ]
     );
 }
+
+#[test]
+fn digests_are_only_compared_within_an_algorithm() {
+    use crate::{ChangeReason, HashAlgorithm};
+
+    let entry = |md5_hash: u128, digest: crate::Digest| FileEntry {
+        path: "file".into(),
+        size: Some(4),
+        md5_hash: Some(md5_hash),
+        digest: Some(digest),
+        ..Default::default()
+    };
+    let sha256 = |bytes: &[u8]| HashAlgorithm::Sha256.digest(bytes);
+    let crc32c = |bytes: &[u8]| HashAlgorithm::Crc32c.digest(bytes);
+
+    // Matching digests don't override differing MD5 hashes
+    assert_eq!(
+        entry(1, sha256(b"same"))
+            .compare(&entry(2, sha256(b"same")))
+            .unwrap(),
+        ChangeReason::SizeOrHashDiffers
+    );
+    assert_eq!(
+        entry(1, sha256(b"same"))
+            .compare(&entry(1, sha256(b"same")))
+            .unwrap(),
+        ChangeReason::SameSizeAndHash
+    );
+    assert_eq!(
+        entry(1, sha256(b"same"))
+            .compare(&entry(1, sha256(b"diff")))
+            .unwrap(),
+        ChangeReason::SizeOrHashDiffers
+    );
+
+    // Digests from different algorithms fall back to MD5
+    assert_eq!(
+        entry(1, sha256(b"same"))
+            .compare(&entry(1, crc32c(b"diff")))
+            .unwrap(),
+        ChangeReason::SameSizeAndHash
+    );
+    assert_eq!(crc32c(b"123456789").value, vec![0xe3, 0x06, 0x92, 0x83]);
+    assert_eq!(
+        HashAlgorithm::Blake3.digest(b"").value,
+        b"\xaf\x13\x49\xb9\xf5\xf9\xa1\xa6\xa0\x40\x4d\xea\x36\xdc\xc9\x49\
+          \x9b\xcb\x25\xc9\xad\xc1\x12\xb7\xcc\x9a\x93\xca\xe4\x1f\x32\x62"
+    );
+
+    // MD5 digests are compared with MD5 hashes, but not with multipart ones
+    let md5 = |bytes: &[u8]| u128::from_be_bytes(md5::compute(bytes).0);
+    let hashed = FileEntry {
+        path: "file".into(),
+        size: Some(4),
+        digest: Some(HashAlgorithm::Md5.digest(b"same")),
+        ..Default::default()
+    };
+    let etag = |hash: u128, parts: Option<u32>| FileEntry {
+        path: "file".into(),
+        size: Some(4),
+        md5_hash: Some(hash),
+        md5_parts: parts,
+        ..Default::default()
+    };
+    assert_eq!(
+        hashed.compare(&etag(md5(b"same"), None)).unwrap(),
+        ChangeReason::SameSizeAndHash
+    );
+    assert_eq!(
+        hashed.compare(&etag(md5(b"diff"), None)).unwrap(),
+        ChangeReason::SizeOrHashDiffers
+    );
+    assert_eq!(
+        hashed.compare(&etag(md5(b"diff"), Some(1))).unwrap(),
+        ChangeReason::SizeOrHashMatches
+    );
+}
+
+fn file_names(source: &TestSource) -> Vec<(String, String)> {
+    let mut names = source
+        .files()
+        .iter()
+        .map(|(entry, bytes)| {
+            (
+                entry.path.display().to_string(),
+                String::from_utf8(bytes.clone()).unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn two_way_sync_copies_new_files_both_ways() {
+    let clock = Arc::new(AtomicU64::new(0));
+
+    let a = TestSource::new(Some(Arc::clone(&clock)), false);
+    let b = TestSource::new(Some(Arc::clone(&clock)), false);
+    let mut state = SyncState::new();
+
+    pollster::block_on(a.write_file("only_a.txt", b"a")).unwrap();
+    pollster::block_on(b.write_file("only_b.txt", b"b")).unwrap();
+
+    let report = pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+
+    assert_eq!(
+        report,
+        TwoWayReport {
+            a: SyncReport {
+                written: vec!["only_b.txt".into()],
+                ..SyncReport::default()
+            },
+            b: SyncReport {
+                written: vec!["only_a.txt".into()],
+                ..SyncReport::default()
+            },
+        }
+    );
+    assert_eq!(file_names(&a), file_names(&b));
+    assert!(state.get("only_a.txt").is_some());
+    assert!(state.get("only_b.txt").is_some());
+
+    // A second sync has nothing to do
+    let report = pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+    assert_eq!(report, TwoWayReport::default());
+}
+
+#[test]
+fn two_way_sync_propagates_deletes() {
+    let a = TestSource::new(None, true);
+    let b = TestSource::new(None, true);
+    let mut state = SyncState::new();
+
+    pollster::block_on(a.write_file("one.txt", b"one")).unwrap();
+    pollster::block_on(a.write_file("two.txt", b"two")).unwrap();
+    pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+
+    pollster::block_on(a.delete_file("one.txt")).unwrap();
+    pollster::block_on(b.delete_file("two.txt")).unwrap();
+
+    let report = pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+
+    assert_eq!(report.a.deleted, vec![PathBuf::from("two.txt")]);
+    assert_eq!(report.b.deleted, vec![PathBuf::from("one.txt")]);
+    assert_eq!(&a.files(), &[]);
+    assert_eq!(&b.files(), &[]);
+    assert_eq!(state, SyncState::new());
+}
+
+#[test]
+fn two_way_sync_uses_state_to_find_edited_side() {
+    let a = TestSource::new(None, false);
+    let b = TestSource::new(None, false);
+    let mut state = SyncState::new();
+
+    pollster::block_on(a.write_file("one.txt", b"one")).unwrap();
+    pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+
+    // Without timestamps, `is_changed_from` alone would copy the stale `a` over `b`
+    pollster::block_on(b.write_file("one.txt", b"one, edited")).unwrap();
+
+    let report = pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+
+    assert_eq!(report.a.written, vec![PathBuf::from("one.txt")]);
+    assert_eq!(report.b, SyncReport::default());
+    assert_eq!(
+        file_names(&a),
+        vec![("one.txt".to_owned(), "one, edited".to_owned())]
+    );
+}
+
+#[test]
+fn two_way_sync_restores_file_edited_on_one_side_and_deleted_on_other() {
+    let a = TestSource::new(None, false);
+    let b = TestSource::new(None, false);
+    let mut state = SyncState::new();
+
+    pollster::block_on(a.write_file("one.txt", b"one")).unwrap();
+    pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+
+    pollster::block_on(a.write_file("one.txt", b"one, edited")).unwrap();
+    pollster::block_on(b.delete_file("one.txt")).unwrap();
+
+    let report = pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+
+    assert_eq!(report.b.written, vec![PathBuf::from("one.txt")]);
+    assert_eq!(
+        file_names(&b),
+        vec![("one.txt".to_owned(), "one, edited".to_owned())]
+    );
+}
+
+#[test]
+fn sync_state_save_load_roundtrip() {
+    let temp: &Path = "./temp/sync_state".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp).unwrap();
+    }
+    std::fs::create_dir_all(temp).unwrap();
+
+    let clock = Arc::new(AtomicU64::new(0));
+    let a = TestSource::new(Some(Arc::clone(&clock)), true);
+    let b = TestSource::new(Some(Arc::clone(&clock)), true);
+    let mut state = SyncState::load("./temp/sync_state/state.json").unwrap();
+    assert_eq!(state, SyncState::new());
+
+    pollster::block_on(a.write_file("one.txt", b"one")).unwrap();
+    pollster::block_on(crate::sync_two_way(&a, &b, &mut state)).unwrap();
+
+    state.save("./temp/sync_state/state.json").unwrap();
+    let loaded = SyncState::load("./temp/sync_state/state.json").unwrap();
+
+    assert_eq!(loaded, state);
+
+    // Saving replaces the file in one step, leaving nothing else behind
+    state.save("./temp/sync_state/state.json").unwrap();
+    let names = std::fs::read_dir(temp)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["state.json"]);
+}
+
+#[test]
+fn mirror_deletes_files_missing_from_source() {
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true);
+
+    pollster::block_on(from.write_file("kept.txt", b"new")).unwrap();
+    pollster::block_on(to.write_file("kept.txt", b"old")).unwrap();
+    pollster::block_on(to.write_file("stale/two.txt", b"two")).unwrap();
+    pollster::block_on(to.write_file("stale/one.txt", b"one")).unwrap();
+
+    let report = pollster::block_on(crate::sync_mirror(&from, &to)).unwrap();
+
+    assert_eq!(
+        report,
+        SyncReport {
+            written: vec!["kept.txt".into()],
+            deleted: vec!["stale/one.txt".into(), "stale/two.txt".into()],
+            skipped: vec![],
+            failed: vec![],
+        }
+    );
+    assert_eq!(
+        file_names(&to),
+        vec![("kept.txt".to_owned(), "new".to_owned())]
+    );
+}
+
+#[test]
+fn sync_one_way_leaves_files_missing_from_source() {
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true);
+
+    pollster::block_on(to.write_file("stale.txt", b"stale")).unwrap();
+
+    let written = pollster::block_on(crate::sync_one_way(&from, &to)).unwrap();
+
+    assert_eq!(written, Vec::<PathBuf>::new());
+    assert_eq!(
+        file_names(&to),
+        vec![("stale.txt".to_owned(), "stale".to_owned())]
+    );
+}
+
+#[test]
+fn plan_lists_every_action_without_writing() {
+    use crate::{ChangeReason, SyncActionKind};
+
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true);
+
+    pollster::block_on(from.write_file("new.txt", b"new")).unwrap();
+    pollster::block_on(from.write_file("changed.txt", b"changed")).unwrap();
+    pollster::block_on(from.write_file("same.txt", b"same")).unwrap();
+
+    pollster::block_on(to.write_file("changed.txt", b"old")).unwrap();
+    pollster::block_on(to.write_file("same.txt", b"same")).unwrap();
+    pollster::block_on(to.write_file("orphan.txt", b"orphan")).unwrap();
+
+    let before = file_names(&to);
+    let plan = pollster::block_on(crate::plan_sync(&from, &to)).unwrap();
+    assert_eq!(file_names(&to), before);
+
+    let summary = plan
+        .actions
+        .iter()
+        .map(|action| {
+            (
+                action.path.display().to_string(),
+                action.kind.clone(),
+                action.reason,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                "new.txt".to_owned(),
+                SyncActionKind::Create,
+                ChangeReason::MissingFromDestination
+            ),
+            (
+                "changed.txt".to_owned(),
+                SyncActionKind::Update,
+                ChangeReason::SizeOrHashDiffers
+            ),
+            (
+                "same.txt".to_owned(),
+                SyncActionKind::Skip,
+                ChangeReason::SameSizeAndHash
+            ),
+            (
+                "orphan.txt".to_owned(),
+                SyncActionKind::Skip,
+                ChangeReason::MissingFromSource
+            ),
+        ]
+    );
+    assert_eq!(plan.actions[1].destination.as_ref().unwrap().size, Some(3));
+
+    let plan = plan.with_deletes();
+    assert_eq!(plan.actions[3].kind, SyncActionKind::Delete);
+
+    let report = pollster::block_on(crate::execute(&from, &to, &plan)).unwrap();
+    assert_eq!(
+        report,
+        SyncReport {
+            written: vec!["new.txt".into(), "changed.txt".into()],
+            deleted: vec!["orphan.txt".into()],
+            skipped: vec!["same.txt".into()],
+            failed: vec![],
+        }
+    );
+    assert_eq!(file_names(&to), file_names(&from));
+}
+
+fn sync_with_strategy(
+    from: &TestSource,
+    to: &TestSource,
+    strategy: crate::ConflictStrategy,
+) -> crate::Result<SyncReport> {
+    pollster::block_on(async {
+        let options = crate::SyncOptions::builder().resolver(strategy).build();
+        crate::sync_with_options(from, to, &options).await
+    })
+}
+
+#[test]
+fn source_wins_overwrites_newer_destination() {
+    let clock = Arc::new(AtomicU64::new(0));
+
+    let from = TestSource::new(Some(Arc::clone(&clock)), true);
+    let to = TestSource::new(Some(Arc::clone(&clock)), true);
+
+    pollster::block_on(from.write_file("one.txt", b"source")).unwrap();
+    pollster::block_on(from.write_file("two.txt", b"same")).unwrap();
+    pollster::block_on(to.write_file("one.txt", b"newer destination")).unwrap();
+    pollster::block_on(to.write_file("two.txt", b"same")).unwrap();
+
+    let report = sync_with_strategy(&from, &to, crate::ConflictStrategy::SourceWins).unwrap();
+
+    assert_eq!(report.written, vec![PathBuf::from("one.txt")]);
+    assert_eq!(file_names(&to), file_names(&from));
+}
+
+#[test]
+fn larger_wins_keeps_larger_destination() {
+    let from = TestSource::new(None, false);
+    let to = TestSource::new(None, false);
+
+    pollster::block_on(from.write_file("larger_in_from.txt", b"larger")).unwrap();
+    pollster::block_on(from.write_file("larger_in_to.txt", b"small")).unwrap();
+    pollster::block_on(to.write_file("larger_in_from.txt", b"small")).unwrap();
+    pollster::block_on(to.write_file("larger_in_to.txt", b"larger")).unwrap();
+
+    let report = sync_with_strategy(&from, &to, crate::ConflictStrategy::LargerWins).unwrap();
+
+    assert_eq!(report.written, vec![PathBuf::from("larger_in_from.txt")]);
+    assert_eq!(
+        file_names(&to),
+        vec![
+            ("larger_in_from.txt".to_owned(), "larger".to_owned()),
+            ("larger_in_to.txt".to_owned(), "larger".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn keep_both_renames_losing_copy() {
+    let clock = Arc::new(AtomicU64::new(0));
+
+    let from = TestSource::new(Some(Arc::clone(&clock)), true);
+    let to = TestSource::new(Some(Arc::clone(&clock)), true);
+
+    // 2000-01-01 and 2000-01-02
+    pollster::block_on(to.write_file("source_wins.txt", b"old destination")).unwrap();
+    pollster::block_on(from.write_file("dest_wins.txt", b"old source")).unwrap();
+
+    // 2000-01-03 and 2000-01-04
+    pollster::block_on(from.write_file("source_wins.txt", b"new source")).unwrap();
+    pollster::block_on(to.write_file("dest_wins.txt", b"new destination")).unwrap();
+
+    let report = sync_with_strategy(&from, &to, crate::ConflictStrategy::KeepBoth).unwrap();
+
+    assert_eq!(
+        report.written,
+        vec![
+            PathBuf::from("dest_wins.txt.conflict-20000102T000000Z"),
+            PathBuf::from("source_wins.txt.conflict-20000101T000000Z"),
+            PathBuf::from("source_wins.txt"),
+        ]
+    );
+    assert_eq!(
+        file_names(&to),
+        vec![
+            ("dest_wins.txt".to_owned(), "new destination".to_owned()),
+            (
+                "dest_wins.txt.conflict-20000102T000000Z".to_owned(),
+                "old source".to_owned()
+            ),
+            ("source_wins.txt".to_owned(), "new source".to_owned()),
+            (
+                "source_wins.txt.conflict-20000101T000000Z".to_owned(),
+                "old destination".to_owned()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn keep_both_is_stable_across_syncs() {
+    for delete in [false, true] {
+        let clock = Arc::new(AtomicU64::new(0));
+        let from = TestSource::new(Some(Arc::clone(&clock)), true);
+        let to = TestSource::new(Some(Arc::clone(&clock)), true);
+        pollster::block_on(async {
+            from.write_file("one.txt", b"old source").await?;
+            to.write_file("one.txt", b"new destination").await
+        })
+        .unwrap();
+
+        let options = crate::SyncOptions::builder()
+            .resolver(crate::ConflictStrategy::KeepBoth)
+            .delete(delete)
+            .build();
+        let sync = || pollster::block_on(crate::sync_with_options(&from, &to, &options)).unwrap();
+
+        let report = sync();
+        assert_eq!(
+            report.written,
+            vec![PathBuf::from("one.txt.conflict-20000101T000000Z")]
+        );
+        let files = file_names(&to);
+
+        // Later syncs neither write the kept copy again nor delete it
+        for _ in 0..3 {
+            let report = sync();
+            assert!(report.written.is_empty());
+            assert!(report.deleted.is_empty());
+            assert_eq!(file_names(&to), files);
+        }
+    }
+}
+
+#[test]
+fn fail_strategy_writes_nothing() {
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true);
+
+    pollster::block_on(from.write_file("new.txt", b"new")).unwrap();
+    pollster::block_on(from.write_file("same.txt", b"same")).unwrap();
+    pollster::block_on(from.write_file("different.txt", b"one")).unwrap();
+    pollster::block_on(to.write_file("same.txt", b"same")).unwrap();
+    pollster::block_on(to.write_file("different.txt", b"two")).unwrap();
+
+    let result = sync_with_strategy(&from, &to, crate::ConflictStrategy::Fail);
+
+    match result {
+        Err(crate::SyncError::ErrorComparing { errors }) => {
+            assert_eq!(errors.len(), 1);
+            assert!(matches!(
+                &errors[0],
+                crate::SyncError::Conflict { filename } if filename == Path::new("different.txt")
+            ));
+        }
+        other => panic!("Expected a conflict, got {:?}", other),
+    }
+    assert_eq!(to.files().len(), 2);
+}
+
+#[test]
+fn differing_hashes_conflict_despite_matching_modified_times() {
+    let sources = || {
+        let clock = Arc::new(AtomicU64::new(0));
+        let from = TestSource::new(Some(Arc::clone(&clock)), true);
+        let to = TestSource::new(Some(Arc::clone(&clock)), true);
+        pollster::block_on(async {
+            from.write_file("one.txt", b"aaa").await?;
+            to.write_file("one.txt", b"bbb").await?;
+            to.set_modified("one.txt", from.files()[0].0.modified)
+                .await
+                .map(|_| ())
+        })
+        .unwrap();
+        (from, to)
+    };
+
+    let (from, to) = sources();
+    let report = sync_with_strategy(&from, &to, crate::ConflictStrategy::SourceWins).unwrap();
+    assert_eq!(report.written, vec![PathBuf::from("one.txt")]);
+    assert_eq!(to.files()[0].1, b"aaa");
+
+    let (from, to) = sources();
+    let result = sync_with_strategy(&from, &to, crate::ConflictStrategy::Fail);
+    assert!(matches!(
+        result,
+        Err(crate::SyncError::ErrorComparing { .. })
+    ));
+    assert_eq!(to.files()[0].1, b"bbb");
+}
+
+#[test]
+fn modified_time_options_control_which_side_is_touched() {
+    use crate::{ModifiedTimeSync, SyncOptions};
+
+    let run = |modified_time: ModifiedTimeSync| {
+        let clock = Arc::new(AtomicU64::new(0));
+        let from = TestSource::new(Some(Arc::clone(&clock)), false);
+        let to = TestSource::new(Some(Arc::clone(&clock)), false).drop_modified_times();
+
+        pollster::block_on(to.write_file("one.txt", b"old")).unwrap();
+        pollster::block_on(from.write_file("one.txt", b"new")).unwrap();
+
+        let options = SyncOptions::builder().modified_time(modified_time).build();
+        pollster::block_on(crate::sync_with_options(&from, &to, &options)).unwrap();
+
+        from.files()[0].0.modified.unwrap()
+    };
+
+    let day_one = chrono::Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap();
+    let day_two = chrono::Utc.with_ymd_and_hms(2000, 1, 2, 0, 0, 0).unwrap();
+
+    assert_eq!(run(ModifiedTimeSync::Both), day_one);
+    assert_eq!(run(ModifiedTimeSync::DestinationOnly), day_two);
+    assert_eq!(run(ModifiedTimeSync::Off), day_two);
+}
+
+#[test]
+fn read_only_source_is_never_written() {
+    use crate::{ModifiedTimeSync, ReadOnly, ReadOnlyError, SyncOptions};
+
+    let clock = Arc::new(AtomicU64::new(0));
+    let inner = TestSource::new(Some(Arc::clone(&clock)), false);
+    pollster::block_on(inner.write_file("one.txt", b"one")).unwrap();
+    let from = ReadOnly(inner);
+    let to = TestSource::new(Some(Arc::clone(&clock)), false);
+
+    let options = SyncOptions::builder()
+        .modified_time(ModifiedTimeSync::DestinationOnly)
+        .build();
+    let report = pollster::block_on(crate::sync_with_options(&from, &to, &options)).unwrap();
+    assert_eq!(report.written, vec![PathBuf::from("one.txt")]);
+    assert_eq!(to.files()[0].1, b"one");
+
+    let attempted = |result: Result<(), ReadOnlyError<TestError>>| matches!(result, Err(ReadOnlyError::WriteAttempted { path, .. }) if path == Path::new("one.txt"));
+    assert!(attempted(pollster::block_on(
+        from.write_file("one.txt", b"two")
+    )));
+    assert!(attempted(pollster::block_on(from.delete_file("one.txt"))));
+    assert!(attempted(
+        pollster::block_on(from.set_modified("one.txt", Some(Utc::now()))).map(|_| ())
+    ));
+    assert_eq!(from.0.files()[0].1, b"one");
+}
+
+#[test]
+fn continue_on_error_reports_failed_files() {
+    let from = TestSource::new(None, true).unreadable("unreadable.txt");
+    pollster::block_on(from.write_file("a.txt", b"a")).unwrap();
+    pollster::block_on(from.write_file("unreadable.txt", b"?")).unwrap();
+    pollster::block_on(from.write_file("same.txt", b"same")).unwrap();
+    pollster::block_on(from.write_file("z.txt", b"z")).unwrap();
+
+    let sync = |continue_on_error: bool| {
+        let to = TestSource::new(None, true);
+        pollster::block_on(to.write_file("same.txt", b"same")).unwrap();
+
+        let options = crate::SyncOptions::builder()
+            .continue_on_error(continue_on_error)
+            .build();
+        let result = pollster::block_on(crate::sync_with_options(&from, &to, &options));
+        (result, file_names(&to))
+    };
+
+    let (result, names) = sync(false);
+    assert!(result.is_err());
+    assert_eq!(names.len(), 2);
+
+    let (result, names) = sync(true);
+    let report = result.unwrap();
+    assert_eq!(
+        report.written,
+        vec![PathBuf::from("a.txt"), PathBuf::from("z.txt")]
+    );
+    assert_eq!(report.skipped, vec![PathBuf::from("same.txt")]);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].path, PathBuf::from("unreadable.txt"));
+    assert!(matches!(
+        *report.failed[0].error,
+        crate::SyncError::FileSourceError(_)
+    ));
+
+    // Reports with failures can still be cloned, and sent to other threads.
+    fn assert_send<T: Send>(_: &T) {}
+    assert_send(&report);
+    assert_eq!(report.clone(), report);
+    assert_eq!(names.len(), 3);
+}
+
+#[test]
+fn transient_errors_are_retried() {
+    use crate::{RetryPolicy, SyncOptions};
+
+    let sync = |failures: u64, retry: RetryPolicy| {
+        let from = TestSource::new(None, true).transient_failures(failures);
+        pollster::block_on(from.write_file("one.txt", b"one")).unwrap();
+        let to = TestSource::new(None, true);
+
+        let options = SyncOptions::builder().retry(retry).build();
+        pollster::block_on(crate::sync_with_options(&from, &to, &options))
+            .map(|report| report.written)
+    };
+    let retry = RetryPolicy::new(3).base_delay(Duration::from_millis(1));
+
+    assert!(sync(1, RetryPolicy::default()).is_err());
+    assert_eq!(
+        sync(2, retry.clone()).unwrap(),
+        vec![PathBuf::from("one.txt")]
+    );
+    assert!(sync(3, retry.clone()).is_err());
+    assert!(sync(1, retry.retry_if(|_| false)).is_err());
+}
+
+#[test]
+fn written_files_are_verified() {
+    use crate::{RetryPolicy, SyncError, SyncOptions};
+
+    let sync = |corruptions: u64, options: SyncOptions| {
+        let from = TestSource::new(None, true);
+        pollster::block_on(from.write_file("one.txt", b"one")).unwrap();
+        // Flip the first byte of the next few files written
+        let corruptions = AtomicU64::new(corruptions);
+        let to = TestSource::new(None, true).on_write(move |_, bytes| {
+            let corrupting = corruptions.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(1)
+            });
+            if corrupting.is_ok() {
+                bytes[0] ^= 0xff;
+            }
+        });
+
+        let result = pollster::block_on(crate::sync_with_options(&from, &to, &options));
+        (result.map(|report| report.written), to.files())
+    };
+    let retry = RetryPolicy::new(3).base_delay(Duration::from_millis(1));
+
+    // Without verification, the corrupted file goes unnoticed.
+    let (written, files) = sync(1, SyncOptions::default());
+    assert_eq!(written.unwrap(), vec![PathBuf::from("one.txt")]);
+    assert_eq!(files[0].1, b"\x90ne");
+
+    let (written, _) = sync(1, SyncOptions::builder().verify(true).build());
+    assert!(matches!(
+        written,
+        Err(SyncError::VerificationFailed { filename, .. }) if filename == Path::new("one.txt")
+    ));
+
+    let options = SyncOptions::builder().verify(true).retry(retry).build();
+    let (written, files) = sync(2, options.clone());
+    assert_eq!(written.unwrap(), vec![PathBuf::from("one.txt")]);
+    assert_eq!(files[0].1, b"one");
+    assert!(sync(3, options).0.is_err());
+
+    // The bytes read are hashed with the destination's algorithm, even if the source
+    // doesn't list any hashes.
+    let from = TestSource::new(None, false);
+    pollster::block_on(from.write_file("one.txt", b"one")).unwrap();
+    let to = TestSource::new(None, false)
+        .digest_algorithm(HashAlgorithm::Blake3)
+        .on_write(|_, bytes| bytes[0] ^= 0xff);
+    let options = SyncOptions::builder().verify(true).build();
+    assert!(matches!(
+        pollster::block_on(crate::sync_with_options(&from, &to, &options)),
+        Err(SyncError::VerificationFailed { reason, .. }) if reason.contains("hash")
+    ));
+}
+
+#[test]
+fn moved_files_are_renamed_in_the_destination() {
+    use crate::{SyncActionKind, SyncOptions};
+
+    let sources = || {
+        let from = TestSource::new(None, true);
+        let to = TestSource::new(None, true);
+        pollster::block_on(async {
+            from.write_file("static/a.txt", b"aaa").await?;
+            from.write_file("static/b.txt", b"bbb").await?;
+            to.write_file("assets/a.txt", b"aaa").await?;
+            to.write_file("assets/b.txt", b"bbb").await?;
+            to.write_file("other.txt", b"ccc").await
+        })
+        .unwrap();
+        (from, to)
+    };
+    let kinds = |plan: &crate::SyncPlan| {
+        plan.actions
+            .iter()
+            .map(|action| (action.path.display().to_string(), action.kind.clone()))
+            .collect::<Vec<_>>()
+    };
+
+    // Without deletes, the old files are kept, so they are copied.
+    let (from, to) = sources();
+    let options = SyncOptions::builder().detect_renames(true).build();
+    let plan = pollster::block_on(crate::plan_sync_with(&from, &to, &options)).unwrap();
+    assert_eq!(
+        kinds(&plan),
+        vec![
+            (
+                "static/a.txt".to_owned(),
+                SyncActionKind::Copy {
+                    from: PathBuf::from("assets/a.txt")
+                }
+            ),
+            (
+                "static/b.txt".to_owned(),
+                SyncActionKind::Copy {
+                    from: PathBuf::from("assets/b.txt")
+                }
+            ),
+            ("assets/a.txt".to_owned(), SyncActionKind::Skip),
+            ("assets/b.txt".to_owned(), SyncActionKind::Skip),
+            ("other.txt".to_owned(), SyncActionKind::Skip),
+        ]
+    );
+    assert_eq!(plan.total_bytes(), 0);
+
+    let (from, to) = sources();
+    let options = SyncOptions::builder()
+        .detect_renames(true)
+        .delete(true)
+        .build();
+    let plan = pollster::block_on(crate::plan_sync_with(&from, &to, &options)).unwrap();
+    assert_eq!(
+        kinds(&plan),
+        vec![
+            (
+                "static/a.txt".to_owned(),
+                SyncActionKind::Rename {
+                    from: PathBuf::from("assets/a.txt")
+                }
+            ),
+            (
+                "static/b.txt".to_owned(),
+                SyncActionKind::Rename {
+                    from: PathBuf::from("assets/b.txt")
+                }
+            ),
+            ("other.txt".to_owned(), SyncActionKind::Delete),
+        ]
+    );
+
+    let report = pollster::block_on(crate::execute_with(&from, &to, &plan, &options)).unwrap();
+    assert_eq!(
+        report.deleted,
+        vec![
+            PathBuf::from("assets/a.txt"),
+            PathBuf::from("assets/b.txt"),
+            PathBuf::from("other.txt"),
+        ]
+    );
+    assert_eq!(file_names(&to), file_names(&from));
+}
+
+#[test]
+fn observer_receives_progress_events() {
+    use crate::{SyncEvent, SyncOptions};
+
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true);
+    pollster::block_on(from.write_file("new.txt", b"new file")).unwrap();
+    pollster::block_on(from.write_file("same.txt", b"same")).unwrap();
+    pollster::block_on(to.write_file("same.txt", b"same")).unwrap();
+    pollster::block_on(to.write_file("orphan.txt", b"orphan")).unwrap();
+
+    let events = Arc::new(Mutex::new(vec![]));
+    let observer_events = Arc::clone(&events);
+    let options = SyncOptions::builder()
+        .delete(true)
+        .observer(move |event: SyncEvent<'_>| {
+            let event = match event {
+                SyncEvent::ListingStarted => "listing started".to_owned(),
+                SyncEvent::ListingFinished {
+                    source_files,
+                    destination_files,
+                } => format!("listed {} and {}", source_files, destination_files),
+                SyncEvent::Planned {
+                    transfers,
+                    deletes,
+                    total_bytes,
+                } => format!(
+                    "planned {} transfers, {} deletes, {} bytes",
+                    transfers, deletes, total_bytes
+                ),
+                SyncEvent::TransferStarted { path, size } => {
+                    format!("started {} ({:?} bytes)", path.display(), size)
+                }
+                SyncEvent::BytesTransferred { path, bytes } => {
+                    format!("transferred {} bytes of {}", bytes, path.display())
+                }
+                SyncEvent::TransferFinished { path } => format!("finished {}", path.display()),
+                SyncEvent::Deleted { path } => format!("deleted {}", path.display()),
+                SyncEvent::Failed { path, .. } => format!("failed {}", path.display()),
+            };
+            observer_events.lock().unwrap().push(event);
+        })
+        .build();
+
+    pollster::block_on(crate::sync_with_options(&from, &to, &options)).unwrap();
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            "listing started",
+            "listed 2 and 2",
+            "planned 1 transfers, 1 deletes, 8 bytes",
+            "started new.txt (Some(8) bytes)",
+            "transferred 8 bytes of new.txt",
+            "finished new.txt",
+            "deleted orphan.txt",
+        ]
+    );
+}
+
+#[test]
+fn filters_apply_to_both_sides() {
+    use crate::{FileFilter, SyncOptions};
+
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true);
+    for path in [
+        "index.html",
+        "blog/post.html",
+        "assets/app.js",
+        "assets/app.js.map",
+        "nested/assets/skipped.js",
+        "README.md",
+    ] {
+        pollster::block_on(from.write_file(path, b"new")).unwrap();
+    }
+    pollster::block_on(to.write_file("old.html", b"old")).unwrap();
+    pollster::block_on(to.write_file("old.js.map", b"old")).unwrap();
+
+    let filter = FileFilter::builder()
+        .include("*.html")
+        .include("/assets/**")
+        .exclude("*.map")
+        .build()
+        .unwrap();
+    let options = SyncOptions::builder().delete(true).filter(filter).build();
+    let report = pollster::block_on(crate::sync_with_options(&from, &to, &options)).unwrap();
+
+    assert_eq!(
+        report.written,
+        vec![
+            PathBuf::from("index.html"),
+            PathBuf::from("blog/post.html"),
+            PathBuf::from("assets/app.js"),
+        ]
+    );
+    assert_eq!(report.deleted, vec![PathBuf::from("old.html")]);
+    assert!(file_names(&to).iter().any(|(name, _)| name == "old.js.map"));
+
+    assert!(matches!(
+        FileFilter::builder().include("{unclosed").build(),
+        Err(crate::SyncError::InvalidPattern { .. })
+    ));
+}
+
+#[test]
+fn transfers_run_concurrently_up_to_limit() {
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true).write_delay(Duration::from_millis(10));
+
+    let paths = (0..10)
+        .map(|i| PathBuf::from(format!("{}.txt", i)))
+        .collect::<Vec<_>>();
+    for path in &paths {
+        pollster::block_on(from.write_file(path, b"contents")).unwrap();
+    }
+
+    let options = crate::SyncOptions::builder().max_concurrency(4).build();
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let report = rt
+        .block_on(crate::sync_with_options(&from, &to, &options))
+        .unwrap();
+
+    assert_eq!(report.written, paths);
+    assert_eq!(to.files().len(), 10);
+    assert_eq!(to.max_writes_in_flight(), 4);
+}
+
+#[test]
+fn transfers_in_flight_finish_after_an_error() {
+    let from = TestSource::new(None, true);
+    let to = TestSource::new(None, true)
+        .write_delay(Duration::from_millis(10))
+        .unwritable("0.txt");
+    for i in 0..8 {
+        pollster::block_on(from.write_file(format!("{}.txt", i), b"contents")).unwrap();
+    }
+
+    let options = crate::SyncOptions::builder().max_concurrency(4).build();
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let result = rt.block_on(crate::sync_with_options(&from, &to, &options));
+    assert!(result.is_err());
+
+    // The three transfers started alongside the failed one finish, and no more start.
+    let mut written = to
+        .files()
+        .into_iter()
+        .map(|(entry, _)| entry.path.display().to_string())
+        .collect::<Vec<_>>();
+    written.sort();
+    assert_eq!(written, ["1.txt", "2.txt", "3.txt"]);
+}
@@ -5,10 +5,10 @@ fn local_to_local() -> Result<()> {
     clear_files()?;
     setup_files()?;
 
-    let mut local_a = filesync::local::LocalFiles::new("./temp/local_local_sync/local_a", false);
-    let mut local_b = filesync::local::LocalFiles::new("./temp/local_local_sync/local_b", false);
+    let local_a = filesync::local::LocalFiles::new("./temp/local_local_sync/local_a", false);
+    let local_b = filesync::local::LocalFiles::new("./temp/local_local_sync/local_b", false);
 
-    pollster::block_on(filesync::sync_one_way(&mut local_a, &mut local_b))?;
+    pollster::block_on(filesync::sync_one_way(&local_a, &local_b))?;
 
     let files = [
         std::fs::read_to_string("./temp/local_local_sync/local_b/file_a.txt")?,
@@ -55,3 +55,112 @@ fn setup_files() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn local_to_local_mirror() -> Result<()> {
+    use std::fs;
+
+    let path: &std::path::Path = "./temp/local_local_mirror".as_ref();
+    if path.exists() {
+        fs::remove_dir_all(path)?;
+    }
+
+    fs::create_dir_all("./temp/local_local_mirror/local_a")?;
+    fs::create_dir_all("./temp/local_local_mirror/local_b/old_folder")?;
+
+    fs::write("./temp/local_local_mirror/local_a/file_a.txt", "file_a")?;
+    fs::write("./temp/local_local_mirror/local_b/file_b.txt", "file_b")?;
+    fs::write(
+        "./temp/local_local_mirror/local_b/old_folder/file_c.txt",
+        "file_c",
+    )?;
+
+    let local_a = filesync::local::LocalFiles::new("./temp/local_local_mirror/local_a", false);
+    let local_b = filesync::local::LocalFiles::new("./temp/local_local_mirror/local_b", false);
+
+    let mut report = pollster::block_on(filesync::sync_mirror(&local_a, &local_b))?;
+    report.deleted.sort();
+
+    assert_eq!(report.written, vec![std::path::PathBuf::from("file_a.txt")]);
+    assert_eq!(
+        report.deleted,
+        vec![
+            std::path::PathBuf::from("file_b.txt"),
+            std::path::PathBuf::from("old_folder/file_c.txt"),
+        ]
+    );
+    assert!(!path.join("local_b/file_b.txt").exists());
+    assert!(!path.join("local_b/old_folder/file_c.txt").exists());
+    assert_eq!(
+        fs::read_to_string("./temp/local_local_mirror/local_b/file_a.txt")?,
+        "file_a"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn destination_is_never_half_written() -> Result<()> {
+    use filesync::{FileSource, SyncEvent, SyncOptions, local::LocalFiles};
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    let path: &std::path::Path = "./temp/local_local_atomic".as_ref();
+    if path.exists() {
+        fs::remove_dir_all(path)?;
+    }
+    fs::create_dir_all(path.join("local_a"))?;
+    fs::create_dir_all(path.join("local_b"))?;
+
+    let new = (0..200 * 1024).map(|i| i as u8).collect::<Vec<u8>>();
+    fs::write(path.join("local_a/file.bin"), &new)?;
+    fs::write(path.join("local_b/file.bin"), "old")?;
+    fs::File::options()
+        .write(true)
+        .open(path.join("local_b/file.bin"))?
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000))?;
+
+    // Left behind by a write that never finished
+    fs::write(
+        path.join("local_b/.file.bin.0123456789abcdef.tmp"),
+        "partial",
+    )?;
+
+    let local_a = LocalFiles::new(path.join("local_a"), false);
+    let local_b = LocalFiles::new(path.join("local_b"), false).include_hidden(true);
+
+    // Look at the destination every time a chunk is read, while the new file is being written
+    let seen = Arc::new(Mutex::new(vec![]));
+    let observer_seen = Arc::clone(&seen);
+    let observer_path = path.join("local_b");
+    let options = SyncOptions::builder()
+        .observer(move |event: SyncEvent<'_>| {
+            if let SyncEvent::BytesTransferred { .. } = event {
+                let listing = LocalFiles::new(&observer_path, false).include_hidden(true);
+                let files = pollster::block_on(listing.list_files()).unwrap();
+                let contents = fs::read(observer_path.join("file.bin")).unwrap();
+                observer_seen.lock().unwrap().push((files.len(), contents));
+            }
+        })
+        .build();
+
+    let report = pollster::block_on(filesync::sync_with_options(&local_a, &local_b, &options))?;
+    assert_eq!(report.written, vec![std::path::PathBuf::from("file.bin")]);
+
+    let seen = seen.lock().unwrap();
+    assert!(seen.len() > 1);
+    for (files, contents) in seen.iter() {
+        assert_eq!(*files, 1);
+        assert_eq!(contents, b"old");
+    }
+    assert_eq!(fs::read(path.join("local_b/file.bin"))?, new);
+
+    let listed = pollster::block_on(local_b.list_files())?;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].path, std::path::PathBuf::from("file.bin"));
+
+    Ok(())
+}
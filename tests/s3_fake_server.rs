@@ -0,0 +1,1128 @@
+//! Tests `S3Files` against a small in-process stand-in for S3, so they can run without AWS
+//! credentials.
+
+#![cfg(feature = "s3")]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use filesync::{
+    FileSource,
+    s3::{MIN_PART_SIZE, S3Files},
+};
+use futures::stream;
+use pretty_assertions::assert_eq;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const PAGE_SIZE: usize = 1000;
+
+#[derive(Default)]
+struct FakeBucket {
+    objects: BTreeMap<String, FakeObject>,
+    uploads: HashMap<String, FakeUpload>,
+    next_upload_id: usize,
+    fail_part: Option<i32>,
+    fail_head: Option<u16>,
+    requests: Vec<String>,
+}
+
+#[derive(Clone, Default)]
+struct FakeObject {
+    bytes: Vec<u8>,
+    etag: String,
+    metadata: BTreeMap<String, String>,
+    content_type: Option<String>,
+    cache_control: Option<String>,
+    checksums: BTreeMap<String, String>,
+}
+
+struct FakeUpload {
+    key: String,
+    metadata: BTreeMap<String, String>,
+    parts: BTreeMap<i32, Vec<u8>>,
+}
+
+#[derive(Clone)]
+struct FakeS3 {
+    bucket: Arc<Mutex<FakeBucket>>,
+    endpoint: String,
+}
+
+impl FakeS3 {
+    async fn start() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        let bucket = Arc::new(Mutex::new(FakeBucket::default()));
+
+        let server_bucket = Arc::clone(&bucket);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let bucket = Arc::clone(&server_bucket);
+                tokio::spawn(async move {
+                    let _ = serve_connection(stream, bucket).await;
+                });
+            }
+        });
+
+        Ok(FakeS3 { bucket, endpoint })
+    }
+
+    fn client(&self) -> aws_sdk_s3::Client {
+        use aws_sdk_s3::config::{Credentials, Region};
+
+        let config = aws_sdk_s3::Config::builder()
+            .endpoint_url(&self.endpoint)
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .force_path_style(true)
+            .build();
+        aws_sdk_s3::Client::from_conf(config)
+    }
+
+    fn insert(&self, key: &str, bytes: &[u8]) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.objects.insert(
+            key.to_owned(),
+            FakeObject {
+                bytes: bytes.to_owned(),
+                etag: format!("{:x}", md5::compute(bytes)),
+                ..FakeObject::default()
+            },
+        );
+    }
+
+    fn object(&self, key: &str) -> Option<FakeObject> {
+        self.bucket.lock().unwrap().objects.get(key).cloned()
+    }
+
+    fn fail_part(&self, part_number: i32) {
+        self.bucket.lock().unwrap().fail_part = Some(part_number);
+    }
+
+    /// Answers every `HEAD` request with `status` and no body, as S3 does for errors.
+    fn fail_head(&self, status: u16) {
+        self.bucket.lock().unwrap().fail_head = Some(status);
+    }
+
+    fn pending_uploads(&self) -> usize {
+        self.bucket.lock().unwrap().uploads.len()
+    }
+
+    /// The name of each S3 operation received, in order.
+    fn requests(&self) -> Vec<String> {
+        self.bucket.lock().unwrap().requests.clone()
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn key(&self) -> String {
+        self.path
+            .trim_start_matches('/')
+            .split_once('/')
+            .map(|(_, key)| key.to_owned())
+            .unwrap_or_default()
+    }
+
+    fn has_query(&self, name: &str) -> bool {
+        self.query.contains_key(name)
+    }
+
+    fn metadata(&self) -> BTreeMap<String, String> {
+        self.headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.strip_prefix("x-amz-meta-")?;
+                Some((name.to_owned(), value.clone()))
+            })
+            .collect()
+    }
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+async fn serve_connection(
+    stream: tokio::net::TcpStream,
+    bucket: Arc<Mutex<FakeBucket>>,
+) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    loop {
+        let mut request_line = String::new();
+        if stream.read_line(&mut request_line).await? == 0 {
+            return Ok(());
+        }
+
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.to_ascii_lowercase(), value.trim().to_owned());
+            }
+        }
+
+        let content_length = match headers.get("content-length") {
+            Some(length) => length.parse()?,
+            None => 0,
+        };
+        let mut body = vec![0; content_length];
+        stream.read_exact(&mut body).await?;
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_owned();
+        let target = parts.next().unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let request = Request {
+            method,
+            path: percent_decode(path),
+            query: query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (percent_decode(key), percent_decode(value))
+                })
+                .collect(),
+            headers,
+            body,
+        };
+
+        let response = handle(&mut bucket.lock().unwrap(), request);
+
+        let mut head = format!("HTTP/1.1 {} OK\r\n", response.status);
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !response
+            .headers
+            .iter()
+            .any(|(name, _)| name == "content-length")
+        {
+            head.push_str(&format!("content-length: {}\r\n", response.body.len()));
+        }
+        head.push_str("\r\n");
+
+        let stream = stream.get_mut();
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&response.body).await?;
+        stream.flush().await?;
+    }
+}
+
+impl Response {
+    fn ok() -> Self {
+        Response {
+            status: 200,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    fn xml(xml: String) -> Self {
+        Response {
+            status: 200,
+            headers: vec![("content-type".to_owned(), "application/xml".to_owned())],
+            body: xml.into_bytes(),
+        }
+    }
+
+    fn error(status: u16, code: &str) -> Self {
+        Response {
+            status,
+            headers: vec![("content-type".to_owned(), "application/xml".to_owned())],
+            body: format!(
+                "<Error><Code>{}</Code><Message>{}</Message></Error>",
+                code, code
+            )
+            .into_bytes(),
+        }
+    }
+}
+
+fn handle(bucket: &mut FakeBucket, request: Request) -> Response {
+    let (operation, response) = match request.method.as_str() {
+        "GET" if request.query.get("list-type").map(String::as_str) == Some("2") => {
+            ("ListObjectsV2", list_objects(bucket, &request))
+        }
+        "GET" => ("GetObject", get_object(bucket, &request)),
+        "HEAD" => ("HeadObject", head_object(bucket, &request)),
+        "PUT" if request.has_query("partNumber") => ("UploadPart", upload_part(bucket, &request)),
+        "PUT" if request.headers.contains_key("x-amz-copy-source") => {
+            ("CopyObject", copy_object(bucket, &request))
+        }
+        "PUT" => ("PutObject", put_object(bucket, &request)),
+        "POST" if request.has_query("uploads") => (
+            "CreateMultipartUpload",
+            create_multipart_upload(bucket, &request),
+        ),
+        "POST" if request.has_query("uploadId") => (
+            "CompleteMultipartUpload",
+            complete_multipart_upload(bucket, &request),
+        ),
+        "DELETE" if request.has_query("uploadId") => {
+            bucket.uploads.remove(&request.query["uploadId"]);
+            ("AbortMultipartUpload", Response::ok())
+        }
+        "DELETE" => {
+            bucket.objects.remove(&request.key());
+            ("DeleteObject", Response::ok())
+        }
+        _ => ("Unknown", Response::error(501, "NotImplemented")),
+    };
+
+    bucket.requests.push(operation.to_owned());
+    response
+}
+
+fn get_object(bucket: &FakeBucket, request: &Request) -> Response {
+    match bucket.objects.get(&request.key()) {
+        Some(object) => Response {
+            status: 200,
+            headers: vec![("etag".to_owned(), format!("\"{}\"", object.etag))],
+            body: object.bytes.clone(),
+        },
+        None => Response::error(404, "NoSuchKey"),
+    }
+}
+
+fn head_object(bucket: &FakeBucket, request: &Request) -> Response {
+    if let Some(status) = bucket.fail_head {
+        return Response {
+            status,
+            headers: vec![],
+            body: vec![],
+        };
+    }
+
+    match bucket.objects.get(&request.key()) {
+        Some(object) => {
+            let mut headers = vec![
+                ("etag".to_owned(), format!("\"{}\"", object.etag)),
+                ("content-length".to_owned(), object.bytes.len().to_string()),
+            ];
+            if let Some(content_type) = &object.content_type {
+                headers.push(("content-type".to_owned(), content_type.clone()));
+            }
+            if let Some(cache_control) = &object.cache_control {
+                headers.push(("cache-control".to_owned(), cache_control.clone()));
+            }
+            for (name, value) in &object.metadata {
+                headers.push((format!("x-amz-meta-{}", name), value.clone()));
+            }
+            if request
+                .headers
+                .get("x-amz-checksum-mode")
+                .map(String::as_str)
+                == Some("ENABLED")
+            {
+                headers.extend(object.checksums.clone());
+            }
+
+            Response {
+                status: 200,
+                headers,
+                body: vec![],
+            }
+        }
+        None => Response::error(404, "NoSuchKey"),
+    }
+}
+
+fn put_object(bucket: &mut FakeBucket, request: &Request) -> Response {
+    let etag = format!("{:x}", md5::compute(&request.body));
+    bucket.objects.insert(
+        request.key(),
+        FakeObject {
+            bytes: request.body.clone(),
+            etag: etag.clone(),
+            metadata: request.metadata(),
+            content_type: request.headers.get("content-type").cloned(),
+            cache_control: request.headers.get("cache-control").cloned(),
+            checksums: request
+                .headers
+                .iter()
+                .filter(|(name, _)| name.starts_with("x-amz-checksum-"))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        },
+    );
+
+    Response {
+        status: 200,
+        headers: vec![("etag".to_owned(), format!("\"{}\"", etag))],
+        body: vec![],
+    }
+}
+
+fn copy_object(bucket: &mut FakeBucket, request: &Request) -> Response {
+    let source = percent_decode(&request.headers["x-amz-copy-source"]);
+    let source_key = source
+        .trim_start_matches('/')
+        .split_once('/')
+        .map(|(_, key)| key.to_owned())
+        .unwrap_or_default();
+    let Some(mut object) = bucket.objects.get(&source_key).cloned() else {
+        return Response::error(404, "NoSuchKey");
+    };
+
+    if request
+        .headers
+        .get("x-amz-metadata-directive")
+        .map(String::as_str)
+        == Some("REPLACE")
+    {
+        object.metadata = request.metadata();
+        object.content_type = request.headers.get("content-type").cloned();
+        object.cache_control = request.headers.get("cache-control").cloned();
+    }
+    object.etag = format!("{:x}", md5::compute(&object.bytes));
+    let etag = object.etag.clone();
+    bucket.objects.insert(request.key(), object);
+
+    Response::xml(format!(
+        "<CopyObjectResult><ETag>\"{}\"</ETag><LastModified>2020-01-01T00:00:00.000Z</LastModified></CopyObjectResult>",
+        etag
+    ))
+}
+
+fn create_multipart_upload(bucket: &mut FakeBucket, request: &Request) -> Response {
+    bucket.next_upload_id += 1;
+    let upload_id = bucket.next_upload_id.to_string();
+    bucket.uploads.insert(
+        upload_id.clone(),
+        FakeUpload {
+            key: request.key(),
+            metadata: request.metadata(),
+            parts: BTreeMap::new(),
+        },
+    );
+
+    Response::xml(format!(
+        "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+        request.key(),
+        upload_id
+    ))
+}
+
+fn upload_part(bucket: &mut FakeBucket, request: &Request) -> Response {
+    let part_number: i32 = request.query["partNumber"].parse().unwrap();
+    if bucket.fail_part == Some(part_number) {
+        return Response::error(400, "InvalidPart");
+    }
+
+    let Some(upload) = bucket.uploads.get_mut(&request.query["uploadId"]) else {
+        return Response::error(404, "NoSuchUpload");
+    };
+    upload.parts.insert(part_number, request.body.clone());
+
+    Response {
+        status: 200,
+        headers: vec![(
+            "etag".to_owned(),
+            format!("\"{:x}\"", md5::compute(&request.body)),
+        )],
+        body: vec![],
+    }
+}
+
+fn complete_multipart_upload(bucket: &mut FakeBucket, request: &Request) -> Response {
+    let Some(upload) = bucket.uploads.remove(&request.query["uploadId"]) else {
+        return Response::error(404, "NoSuchUpload");
+    };
+
+    let body = String::from_utf8_lossy(&request.body);
+    let part_numbers = body
+        .split("<PartNumber>")
+        .skip(1)
+        .map(|rest| rest.split('<').next().unwrap().parse::<i32>().unwrap())
+        .collect::<Vec<_>>();
+
+    // Multipart ETags are the MD5 of the concatenated part MD5s, followed by the part count.
+    let mut bytes = vec![];
+    let mut part_hashes = vec![];
+    for part_number in &part_numbers {
+        let part = &upload.parts[part_number];
+        bytes.extend_from_slice(part);
+        part_hashes.extend_from_slice(&md5::compute(part).0);
+    }
+    let etag = format!("{:x}-{}", md5::compute(&part_hashes), part_numbers.len());
+
+    bucket.objects.insert(
+        upload.key.clone(),
+        FakeObject {
+            bytes,
+            etag: etag.clone(),
+            metadata: upload.metadata.clone(),
+            ..FakeObject::default()
+        },
+    );
+
+    Response::xml(format!(
+        "<CompleteMultipartUploadResult><Bucket>bucket</Bucket><Key>{}</Key><ETag>\"{}\"</ETag></CompleteMultipartUploadResult>",
+        upload.key, etag
+    ))
+}
+
+fn list_objects(bucket: &FakeBucket, request: &Request) -> Response {
+    let prefix = request.query.get("prefix").cloned().unwrap_or_default();
+    let start = request
+        .query
+        .get("continuation-token")
+        .map(|token| token.parse::<usize>().unwrap())
+        .unwrap_or(0);
+
+    let matching = bucket
+        .objects
+        .iter()
+        .filter(|(key, _)| key.starts_with(&prefix))
+        .collect::<Vec<_>>();
+    let page = matching
+        .iter()
+        .skip(start)
+        .take(PAGE_SIZE)
+        .collect::<Vec<_>>();
+    let next = start + page.len();
+    let truncated = next < matching.len();
+
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?><ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+    );
+    xml.push_str(&format!(
+        "<Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+        prefix,
+        page.len(),
+        PAGE_SIZE,
+        truncated
+    ));
+    if truncated {
+        xml.push_str(&format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            next
+        ));
+    }
+    for (key, object) in page {
+        xml.push_str(&format!(
+            "<Contents><Key>{}</Key><LastModified>2020-01-01T00:00:00.000Z</LastModified><ETag>\"{}\"</ETag><Size>{}</Size></Contents>",
+            key,
+            object.etag,
+            object.bytes.len()
+        ));
+    }
+    xml.push_str("</ListBucketResult>");
+
+    Response::xml(xml)
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+                output.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 3;
+            }
+            b'+' => {
+                output.push(b' ');
+                i += 1;
+            }
+            byte => {
+                output.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(output).unwrap()
+}
+
+#[tokio::test]
+async fn list_files_follows_continuation_tokens() -> Result<()> {
+    let s3 = FakeS3::start().await?;
+
+    for i in 0..2500 {
+        s3.insert(&format!("many/file_{:04}.txt", i), b"contents");
+    }
+    s3.insert("other/file.txt", b"not listed");
+
+    let files = S3Files::new(s3.client(), "bucket", "many", true);
+    let listed = files.list_files().await?;
+
+    assert_eq!(listed.len(), 2500);
+    assert_eq!(listed[0].path, std::path::PathBuf::from("file_0000.txt"));
+    assert_eq!(listed[2499].path, std::path::PathBuf::from("file_2499.txt"));
+    assert_eq!(listed[2499].size, Some(8));
+    assert!(listed.iter().all(|file| file.md5_hash.is_some()));
+
+    assert_eq!(s3.requests(), vec!["ListObjectsV2"; 3]);
+
+    Ok(())
+}
+
+fn test_contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn small_files_use_a_single_put() -> Result<()> {
+    let s3 = FakeS3::start().await?;
+    let files = S3Files::new(s3.client(), "bucket", "small", true);
+
+    files.write_file("file.txt", b"Hello").await?;
+
+    assert_eq!(s3.object("small/file.txt").unwrap().bytes, b"Hello");
+    assert_eq!(s3.requests(), vec!["PutObject"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn large_files_are_uploaded_in_parts() -> Result<()> {
+    let s3 = FakeS3::start().await?;
+    let files = S3Files::new(s3.client(), "bucket", "large", true)
+        .multipart_threshold(MIN_PART_SIZE)
+        .part_size(MIN_PART_SIZE)
+        .max_parallel_parts(2);
+
+    let contents = test_contents(MIN_PART_SIZE as usize * 2 + 1000);
+    let chunks = contents
+        .chunks(64 * 1024)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+        .collect::<Vec<_>>();
+    files
+        .write_stream("file.bin", Box::pin(stream::iter(chunks)))
+        .await?;
+
+    let object = s3.object("large/file.bin").unwrap();
+    assert!(object.bytes == contents);
+    assert!(object.etag.ends_with("-3"));
+
+    let mut requests = s3.requests();
+    requests.sort();
+    assert_eq!(
+        requests,
+        vec![
+            "CompleteMultipartUpload",
+            "CreateMultipartUpload",
+            "UploadPart",
+            "UploadPart",
+            "UploadPart",
+        ]
+    );
+    assert_eq!(s3.pending_uploads(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn parts_keep_uploading_while_the_next_is_read() -> Result<()> {
+    use futures::StreamExt;
+
+    let s3 = FakeS3::start().await?;
+    let files = S3Files::new(s3.client(), "bucket", "large", true)
+        .multipart_threshold(MIN_PART_SIZE)
+        .part_size(MIN_PART_SIZE)
+        .max_parallel_parts(2);
+
+    // The last chunk is only read once the first part has reached the server, which never
+    // happens if the upload isn't polled while the stream is.
+    let contents = test_contents(MIN_PART_SIZE as usize + 1000);
+    let (first, last) = contents.split_at(MIN_PART_SIZE as usize + 500);
+    let last = Bytes::copy_from_slice(last);
+    let bucket = s3.clone();
+    let chunks =
+        stream::iter([Ok(Bytes::copy_from_slice(first))]).chain(stream::once(async move {
+            for _ in 0..500 {
+                if bucket.requests().contains(&"UploadPart".to_owned()) {
+                    return Ok(last);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            Err(std::io::Error::other("the first part never uploaded"))
+        }));
+    files.write_stream("file.bin", Box::pin(chunks)).await?;
+
+    assert!(s3.object("large/file.bin").unwrap().bytes == contents);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_multipart_uploads_are_aborted() -> Result<()> {
+    let s3 = FakeS3::start().await?;
+    let files = S3Files::new(s3.client(), "bucket", "large", true).multipart_threshold(0);
+    s3.fail_part(2);
+
+    let contents = test_contents(MIN_PART_SIZE as usize * 2);
+    assert!(files.write_file("file.bin", &contents).await.is_err());
+
+    assert!(s3.object("large/file.bin").is_none());
+    assert_eq!(s3.pending_uploads(), 0);
+    assert_eq!(s3.requests().last().unwrap(), "AbortMultipartUpload");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn errors_are_transient_by_http_status() -> Result<()> {
+    let s3 = FakeS3::start().await?;
+    s3.insert("site/file.txt", b"Hello");
+    let files = S3Files::new(s3.client(), "bucket", "site", true);
+
+    s3.fail_head(403);
+    let error = files.stat_file("file.txt").await.unwrap_err();
+    assert!(!error.is_transient());
+
+    s3.fail_head(503);
+    let error = files.stat_file("file.txt").await.unwrap_err();
+    assert!(error.is_transient());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn multipart_etags_compare_with_local_multipart_hashes() -> Result<()> {
+    use filesync::local::LocalFiles;
+
+    let temp: &std::path::Path = "./temp/s3_multipart_etags".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp)?;
+    }
+    std::fs::create_dir_all(temp)?;
+    std::fs::write(
+        temp.join("large.bin"),
+        test_contents(MIN_PART_SIZE as usize + 1),
+    )?;
+
+    let s3 = FakeS3::start().await?;
+    let remote = S3Files::new(s3.client(), "bucket", "site", true)
+        .multipart_threshold(MIN_PART_SIZE)
+        .part_size(MIN_PART_SIZE);
+    let local = LocalFiles::new(temp, true).multipart_md5(MIN_PART_SIZE, MIN_PART_SIZE);
+
+    let written = filesync::sync_one_way(&local, &remote).await?;
+    assert_eq!(written.len(), 1);
+
+    let listed = remote.list_files().await?;
+    assert_eq!(listed[0].md5_parts, Some(2));
+    assert_eq!(listed[0].md5_hash, local.list_files().await?[0].md5_hash);
+
+    let written = filesync::sync_one_way(&local, &remote).await?;
+    assert!(written.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn modified_times_are_stored_in_metadata() -> Result<()> {
+    use chrono::TimeZone;
+
+    let s3 = FakeS3::start().await?;
+    let files = S3Files::new(s3.client(), "bucket", "site", true).store_modified_times(true);
+    let modified = chrono::Utc.with_ymd_and_hms(2001, 2, 3, 4, 5, 6).unwrap();
+
+    files.write_file("a file.txt", b"Hello").await?;
+    assert!(files.set_modified("a file.txt", Some(modified)).await?);
+
+    let object = s3.object("site/a file.txt").unwrap();
+    assert_eq!(object.bytes, b"Hello");
+    assert!(object.metadata.contains_key("mtime"));
+
+    let listed = files.list_files().await?;
+    assert_eq!(listed[0].modified, Some(modified));
+
+    // Without the option, listing falls back to the upload time
+    let plain = S3Files::new(s3.client(), "bucket", "site", true);
+    assert_ne!(plain.list_files().await?[0].modified, Some(modified));
+    assert!(!plain.set_modified("a file.txt", Some(modified)).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn modified_times_are_stored_with_uploads() -> Result<()> {
+    use chrono::TimeZone;
+    use filesync::FileAttributes;
+
+    let s3 = FakeS3::start().await?;
+    let files = S3Files::new(s3.client(), "bucket", "site", true)
+        .store_modified_times(true)
+        .multipart_threshold(MIN_PART_SIZE)
+        .part_size(MIN_PART_SIZE);
+    let modified = chrono::Utc.with_ymd_and_hms(2001, 2, 3, 4, 5, 6).unwrap();
+    let attributes = FileAttributes {
+        modified: Some(modified),
+        ..Default::default()
+    };
+
+    let contents = Bytes::from(test_contents(MIN_PART_SIZE as usize + 1000));
+    let stored = files
+        .write_stream_with(
+            "large.bin",
+            Box::pin(stream::iter([Ok(contents)])),
+            attributes,
+        )
+        .await?;
+    assert_eq!(stored, attributes);
+
+    let object = s3.object("site/large.bin").unwrap();
+    assert!(object.etag.ends_with("-2"));
+    assert!(object.metadata.contains_key("mtime"));
+    assert!(!s3.requests().contains(&"CopyObject".to_owned()));
+    assert_eq!(files.list_files().await?[0].modified, Some(modified));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn updating_metadata_keeps_other_headers() -> Result<()> {
+    use chrono::TimeZone;
+
+    let s3 = FakeS3::start().await?;
+    s3.client()
+        .put_object()
+        .bucket("bucket")
+        .key("site/page.html")
+        .content_type("text/html")
+        .cache_control("max-age=60")
+        .body(b"<p>Hello</p>".to_vec().into())
+        .send()
+        .await?;
+
+    let files = S3Files::new(s3.client(), "bucket", "site", true).store_modified_times(true);
+    let modified = chrono::Utc.with_ymd_and_hms(2001, 2, 3, 4, 5, 6).unwrap();
+    assert!(files.set_modified("page.html", Some(modified)).await?);
+
+    let object = s3.object("site/page.html").unwrap();
+    assert!(object.metadata.contains_key("mtime"));
+    assert_eq!(object.content_type.as_deref(), Some("text/html"));
+    assert_eq!(object.cache_control.as_deref(), Some("max-age=60"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn syncing_to_s3_leaves_local_modified_times_alone() -> Result<()> {
+    use filesync::local::LocalFiles;
+
+    let temp: &std::path::Path = "./temp/s3_modified_times".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp)?;
+    }
+    std::fs::create_dir_all(temp)?;
+    std::fs::write(temp.join("file.txt"), b"Hello")?;
+    let before = std::fs::metadata(temp.join("file.txt"))?.modified()?;
+
+    let s3 = FakeS3::start().await?;
+    let remote = S3Files::new(s3.client(), "bucket", "site", false).store_modified_times(true);
+    let local = LocalFiles::new(temp, false);
+
+    assert_eq!(filesync::sync_one_way(&local, &remote).await?.len(), 1);
+    assert_eq!(
+        std::fs::metadata(temp.join("file.txt"))?.modified()?,
+        before
+    );
+
+    // The time is stored by the upload itself, rather than by copying the object afterwards.
+    assert!(s3.requests().contains(&"PutObject".to_owned()));
+    assert!(!s3.requests().contains(&"CopyObject".to_owned()));
+    assert!(
+        s3.object("site/file.txt")
+            .unwrap()
+            .metadata
+            .contains_key("mtime")
+    );
+
+    assert!(filesync::sync_one_way(&local, &remote).await?.is_empty());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinks_round_trip_through_metadata() -> Result<()> {
+    use filesync::local::{LocalFiles, SymlinkMode};
+
+    let temp: &std::path::Path = "./temp/s3_symlinks".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp)?;
+    }
+    std::fs::create_dir_all(temp.join("source"))?;
+    std::fs::create_dir_all(temp.join("restored"))?;
+    std::fs::write(temp.join("source/target.txt"), b"Hello")?;
+    std::os::unix::fs::symlink("target.txt", temp.join("source/link.txt"))?;
+
+    let s3 = FakeS3::start().await?;
+    let remote = S3Files::new(s3.client(), "bucket", "site", true).store_symlinks(true);
+    let source = LocalFiles::new(temp.join("source"), true).symlinks(SymlinkMode::Preserve);
+    let restored = LocalFiles::new(temp.join("restored"), true).symlinks(SymlinkMode::Preserve);
+
+    assert_eq!(filesync::sync_one_way(&source, &remote).await?.len(), 2);
+
+    let object = s3.object("site/link.txt").unwrap();
+    assert_eq!(object.bytes, b"target.txt");
+    assert_eq!(object.metadata["symlink-target"], "target.txt");
+
+    assert!(filesync::sync_one_way(&source, &remote).await?.is_empty());
+
+    filesync::sync_one_way(&remote, &restored).await?;
+    assert_eq!(
+        std::fs::read_link(temp.join("restored/link.txt"))?,
+        std::path::Path::new("target.txt")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn permissions_are_stored_in_metadata() -> Result<()> {
+    use filesync::FilePermissions;
+
+    let s3 = FakeS3::start().await?;
+    let files = S3Files::new(s3.client(), "bucket", "site", true).store_permissions(true);
+    let permissions = FilePermissions {
+        mode: 0o755,
+        uid: Some(1000),
+        gid: None,
+    };
+
+    files.write_file("script.sh", b"#!/bin/sh").await?;
+    assert!(files.set_permissions("script.sh", permissions).await?);
+
+    let object = s3.object("site/script.sh").unwrap();
+    assert_eq!(object.bytes, b"#!/bin/sh");
+    assert_eq!(object.metadata["mode"], "755");
+    assert_eq!(object.metadata["uid"], "1000");
+    assert!(!object.metadata.contains_key("gid"));
+
+    let listed = files.list_files().await?;
+    assert_eq!(listed[0].permissions, Some(permissions));
+
+    let plain = S3Files::new(s3.client(), "bucket", "site", true);
+    assert_eq!(plain.list_files().await?[0].permissions, None);
+    assert!(!plain.set_permissions("script.sh", permissions).await?);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn permissions_are_stored_with_uploads() -> Result<()> {
+    use filesync::{SyncOptions, local::LocalFiles};
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp: &std::path::Path = "./temp/s3_upload_permissions".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp)?;
+    }
+    std::fs::create_dir_all(temp)?;
+    std::fs::write(temp.join("script.sh"), b"#!/bin/sh")?;
+    std::fs::set_permissions(
+        temp.join("script.sh"),
+        std::fs::Permissions::from_mode(0o751),
+    )?;
+
+    let s3 = FakeS3::start().await?;
+    let remote = S3Files::new(s3.client(), "bucket", "site", false)
+        .store_modified_times(true)
+        .store_permissions(true);
+    let local = LocalFiles::new(temp, false);
+
+    let options = SyncOptions::builder().permissions(true).build();
+    let report = filesync::sync_with_options(&local, &remote, &options).await?;
+    assert_eq!(report.written.len(), 1);
+
+    // Both the time and the permissions go in the upload, so nothing is copied afterwards.
+    assert_eq!(s3.requests(), ["ListObjectsV2", "PutObject"]);
+    let object = s3.object("site/script.sh").unwrap();
+    assert_eq!(object.metadata["mode"], "751");
+    assert!(object.metadata.contains_key("mtime"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn checksums_compare_with_local_digests() -> Result<()> {
+    use filesync::{ChangeReason, HashAlgorithm, local::LocalFiles};
+
+    let temp: &std::path::Path = "./temp/s3_checksums".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp)?;
+    }
+    std::fs::create_dir_all(temp)?;
+    std::fs::write(temp.join("file.txt"), b"Hello")?;
+
+    let s3 = FakeS3::start().await?;
+    let remote = S3Files::new(s3.client(), "bucket", "site", false)
+        .checksum_algorithm(Some(HashAlgorithm::Sha256));
+    let local = LocalFiles::new(temp, false).hash_algorithm(Some(HashAlgorithm::Sha256));
+
+    assert_eq!(filesync::sync_one_way(&local, &remote).await?.len(), 1);
+    assert!(
+        s3.object("site/file.txt")
+            .unwrap()
+            .checksums
+            .contains_key("x-amz-checksum-sha256")
+    );
+
+    let listed = remote.list_files().await?;
+    assert_eq!(
+        listed[0].digest,
+        Some(HashAlgorithm::Sha256.digest(b"Hello"))
+    );
+
+    let plan = filesync::plan_sync(&local, &remote).await?;
+    assert_eq!(plan.actions[0].reason, ChangeReason::SameSizeAndHash);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn algorithms_without_s3_checksums_are_ignored() -> Result<()> {
+    use filesync::HashAlgorithm;
+
+    let s3 = FakeS3::start().await?;
+    let files = S3Files::new(s3.client(), "bucket", "site", false)
+        .checksum_algorithm(Some(HashAlgorithm::Blake3));
+
+    files.write_file("file.txt", b"Hello").await?;
+    assert!(s3.object("site/file.txt").unwrap().checksums.is_empty());
+
+    let listed = files.list_files().await?;
+    assert_eq!(listed[0].digest, None);
+    assert!(!s3.requests().contains(&"HeadObject".to_owned()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn uploads_are_verified_with_head_object() -> Result<()> {
+    use filesync::{HashAlgorithm, SyncOptions, local::LocalFiles};
+
+    let temp: &std::path::Path = "./temp/s3_verify".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp)?;
+    }
+    std::fs::create_dir_all(temp)?;
+    std::fs::write(temp.join("file.txt"), b"Hello")?;
+
+    let s3 = FakeS3::start().await?;
+    let remote = S3Files::new(s3.client(), "bucket", "site", true)
+        .checksum_algorithm(Some(HashAlgorithm::Crc32c));
+    let local = LocalFiles::new(temp, true).hash_algorithm(Some(HashAlgorithm::Crc32c));
+
+    assert_eq!(remote.stat_file("file.txt").await?, None);
+
+    let options = SyncOptions::builder().verify(true).build();
+    let report = filesync::sync_with_options(&local, &remote, &options).await?;
+    assert_eq!(report.written.len(), 1);
+    assert_eq!(s3.requests().last().unwrap(), "HeadObject");
+
+    let stat = remote.stat_file("file.txt").await?.unwrap();
+    assert_eq!(stat.size, Some(5));
+    assert_eq!(
+        stat.md5_hash,
+        Some(u128::from_be_bytes(md5::compute(b"Hello").into()))
+    );
+    assert_eq!(stat.digest, Some(HashAlgorithm::Crc32c.digest(b"Hello")));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn moved_files_are_copied_server_side() -> Result<()> {
+    use filesync::{SyncOptions, local::LocalFiles};
+
+    let temp: &std::path::Path = "./temp/s3_rename".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp)?;
+    }
+    std::fs::create_dir_all(temp.join("static/assets"))?;
+    std::fs::write(temp.join("static/assets/file.txt"), b"Hello")?;
+
+    let s3 = FakeS3::start().await?;
+    s3.insert("site/assets/file.txt", b"Hello");
+    let remote = S3Files::new(s3.client(), "bucket", "site", true);
+    let local = LocalFiles::new(temp, true);
+
+    let options = SyncOptions::builder()
+        .detect_renames(true)
+        .delete(true)
+        .build();
+    let report = filesync::sync_with_options(&local, &remote, &options).await?;
+    assert_eq!(report.written.len(), 1);
+    assert_eq!(report.deleted.len(), 1);
+
+    let requests = s3.requests();
+    assert!(requests.contains(&"CopyObject".to_owned()));
+    assert!(!requests.contains(&"PutObject".to_owned()));
+    assert!(s3.object("site/assets/file.txt").is_none());
+    assert_eq!(
+        s3.object("site/static/assets/file.txt").unwrap().bytes,
+        b"Hello"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn losing_copies_are_kept_server_side() -> Result<()> {
+    use filesync::{ConflictStrategy, SyncOptions, local::LocalFiles};
+
+    let temp: &std::path::Path = "./temp/s3_keep_both".as_ref();
+    if temp.exists() {
+        std::fs::remove_dir_all(temp)?;
+    }
+    std::fs::create_dir_all(temp)?;
+    std::fs::write(temp.join("file.txt"), b"New")?;
+
+    // Listed as modified in 2020, so the local file wins
+    let s3 = FakeS3::start().await?;
+    s3.insert("site/file.txt", b"Old");
+    let remote = S3Files::new(s3.client(), "bucket", "site", true);
+    let local = LocalFiles::new(temp, true);
+
+    let options = SyncOptions::builder()
+        .resolver(ConflictStrategy::KeepBoth)
+        .build();
+    let report = filesync::sync_with_options(&local, &remote, &options).await?;
+    assert_eq!(report.written.len(), 2);
+
+    let requests = s3.requests();
+    assert!(requests.contains(&"CopyObject".to_owned()));
+    assert!(!requests.contains(&"GetObject".to_owned()));
+    assert_eq!(
+        s3.object("site/file.txt.conflict-20200101T000000Z")
+            .unwrap()
+            .bytes,
+        b"Old"
+    );
+    assert_eq!(s3.object("site/file.txt").unwrap().bytes, b"New");
+
+    Ok(())
+}
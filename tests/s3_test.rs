@@ -30,11 +30,11 @@ async fn run_test() -> Result<()> {
     let config = aws_config::load_from_env().await;
     let client = aws_sdk_s3::Client::new(&config);
 
-    let mut local = filesync::local::LocalFiles::new("./temp/s3_test", true);
-    let mut s3 = filesync::s3::S3Files::new(client, bucket, prefix, true);
+    let local = filesync::local::LocalFiles::new("./temp/s3_test", true);
+    let s3 = filesync::s3::S3Files::new(client, bucket, prefix, true);
 
     eprintln!("1. Syncing initial files to S3");
-    let mut synced_paths = filesync::sync_one_way(&mut local, &mut s3).await?;
+    let mut synced_paths = filesync::sync_one_way(&local, &s3).await?;
     std::thread::sleep(std::time::Duration::from_secs(2));
 
     synced_paths.sort();
@@ -53,7 +53,7 @@ async fn run_test() -> Result<()> {
     std::fs::remove_dir_all("./temp/s3_test/folder")?;
 
     eprintln!("3. Restoring files from S3");
-    let mut synced_paths = filesync::sync_one_way(&mut s3, &mut local).await?;
+    let mut synced_paths = filesync::sync_one_way(&s3, &local).await?;
     std::thread::sleep(std::time::Duration::from_secs(2));
 
     synced_paths.sort();
@@ -71,7 +71,7 @@ async fn run_test() -> Result<()> {
     std::fs::write("./temp/s3_test/folder/four_changes.txt", "four up-to-date")?;
 
     eprintln!("5. Syncing changes to S3");
-    let mut synced_paths = filesync::sync_one_way(&mut local, &mut s3).await?;
+    let mut synced_paths = filesync::sync_one_way(&local, &s3).await?;
     std::thread::sleep(std::time::Duration::from_secs(2));
 
     synced_paths.sort();